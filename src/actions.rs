@@ -1,5 +1,5 @@
-use std::{collections::{HashMap}, future::Future, pin::Pin};
-use crate::{emake::{InFile, PluginAction}, graph::generator::to_footprint_path};
+use std::{collections::{BTreeMap, HashMap}, fmt, future::Future, pin::Pin};
+use crate::{cache::state, emake::{env::StepEnv, for_each::ITEM_INDEX, InFile, PluginAction}};
 
 pub mod shell;
 pub mod copy;
@@ -10,42 +10,70 @@ pub mod archive;
 pub mod git_clone;
 pub mod yaml;
 
-pub fn compute_action_footprint(action: &PluginAction) -> String {
-    let serialized = serde_json::to_vec(action).expect("Failed to serialize PluginAction");
+/// Footprint of the action, of the environment it runs in and of the parameters of the step
+pub fn compute_action_footprint(action: &PluginAction, env: &StepEnv, replacements: &HashMap<String, String>) -> String {
+    let mut serialized = serde_json::to_vec(action).expect("Failed to serialize PluginAction");
+    if let Some(env_footprint) = env.get_footprint() {
+        serialized.extend(serde_json::to_vec(&env_footprint).expect("Failed to serialize the step environment"));
+    }
+    // Parameters and for_each items of the step, the same action can run with other values.
+    // Inserting an item moves the next ones, their position only matters when the action uses it.
+    let uses_item_index = String::from_utf8_lossy(&serialized).contains(ITEM_INDEX);
+    let sorted_replacements: BTreeMap<&String, &String> = replacements
+        .iter()
+        .filter(|(name, _value)| uses_item_index || name.as_str() != ITEM_INDEX)
+        .collect();
+    if !sorted_replacements.is_empty() {
+        serialized.extend(serde_json::to_vec(&sorted_replacements).expect("Failed to serialize the step parameters"));
+    }
     blake3::hash(&serialized).to_hex().to_string()
 }
 
 pub async fn get_registered_action_footprint(id: &str) -> Option<String> {
-    let footprint_path = to_footprint_path(id);
-    let footprint_file_exists = tokio::fs::try_exists(&footprint_path).await.unwrap();
-    if footprint_file_exists {
-        let footprint = tokio::fs::read_to_string(&footprint_path).await.unwrap();
-        return Some(footprint);
-    }
-
-    None
+    state::get_footprint(id)
 }
 
 pub async fn register_action_footprint(id: &str, footprint: &str) {
-    let footprint_path = to_footprint_path(id);
-    tokio::fs::create_dir_all(&footprint_path.parent().unwrap()).await.unwrap();
-    tokio::fs::write(footprint_path, footprint).await.unwrap();
+    state::set_footprint(id, footprint);
+}
+
+/// Error returned by an action running a process, used to report the failure at the end of the build
+#[derive(Debug, Clone)]
+pub struct ActionError {
+    pub message: String,
+    pub exit_code: Option<i32>,
+    /// Last lines written on stderr by the process
+    pub stderr: Vec<String>,
+}
+
+impl fmt::Display for ActionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ActionError {}
+
+/// Step an action runs for
+#[derive(Clone, Copy)]
+pub struct ActionContext<'a> {
+    pub target_id: &'a str,
+    pub step_id: &'a str,
+    pub emakefile_cwd: &'a str,
+    pub maybe_replacements: Option<&'a HashMap<String, String>>,
+    pub env: &'a StepEnv,
 }
 
 pub trait Action: Send + Sync {
     fn run<'a>(
         &'a self,
-        target_id: &'a str,
-        step_id: &'a str,
-        emakefile_cwd: &'a str,
-        silent: bool,
+        context: ActionContext<'a>,
         action: &'a PluginAction,
         in_files: &'a Vec<String>,
         out_file: &'a Vec<String>,
-        working_dir: &'a String,
-        default_replacments: Option<&'a HashMap<String, String>>,
     ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error>>> + Send + 'a>>;
-    fn get_checksum(&self) -> Option<String>;
+    fn get_checksum<'a>(&'a self, action: &'a PluginAction) -> Option<String>;
+    fn get_clean<'a>(&'a self, action: &'a PluginAction) -> Option<String>;
     fn insert_in_files<'a>(&'a self, action: &'a PluginAction, in_files: &'a mut Vec<InFile>) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
     fn insert_out_files<'a>(&'a self, action: &'a PluginAction, out_files: &'a mut Vec<String>) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
     fn clone_box(&self) -> Box<dyn Action + Send + Sync>;
@@ -1,7 +1,6 @@
 use config_macros::ActionDoc;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
     fs,
     future::Future,
     path::{Path, PathBuf},
@@ -19,7 +18,7 @@ use globset::{Glob, GlobSet, GlobSetBuilder};
 use xz2::write::XzEncoder;
 use zstd::stream::Encoder as ZstdEncoder;
 
-use super::Action;
+use super::{Action, ActionContext};
 pub static ID: &str = "archive";
 
 #[derive(ActionDoc, Debug, Clone, Serialize, Deserialize)]
@@ -370,16 +369,12 @@ impl Action for Archive {
 
     fn run<'a>(
         &'a self,
-        target_id: &'a str,
-        step_id: &'a str,
-        _emakefile_cwd: &'a str,
-        _silent: bool,
+        context: ActionContext<'a>,
         action: &'a PluginAction,
         in_files: &'a Vec<String>,
         out_files: &'a Vec<String>,
-        _working_dir: &'a String,
-        _maybe_replacements: Option<&'a HashMap<String, String>>,
     ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error>>> + Send + 'a>> {
+        let ActionContext { target_id, step_id, .. } = context;
         Box::pin(async move {
             let to = &out_files[0];
             let to_path = PathBuf::from(to);
@@ -414,7 +409,10 @@ impl Action for Archive {
         })
     }
 
-    fn get_checksum(&self) -> Option<String> {
+    fn get_checksum<'a>(&'a self, _action: &'a PluginAction) -> Option<String> {
+        None
+    }
+    fn get_clean<'a>(&'a self, _action: &'a PluginAction) -> Option<String> {
         None
     }
 
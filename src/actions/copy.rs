@@ -1,14 +1,14 @@
 use config_macros::ActionDoc;
 use fs_extra::dir::CopyOptions;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, future::Future, path::PathBuf, pin::Pin};
+use std::{fs, future::Future, path::PathBuf, pin::Pin};
 
 use crate::{
     console::log,
     emake::{self, InFile, PluginAction},
 };
 
-use super::Action;
+use super::{Action, ActionContext};
 pub static ID: &str = "copy";
 
 #[derive(ActionDoc, Debug, Clone, Serialize, Deserialize)]
@@ -109,16 +109,12 @@ impl Action for Copy {
 
     fn run<'a>(
         &'a self,
-        _target_id: &'a str,
-        step_id: &'a str,
-        emakefile_cwd: &'a str,
-        _silent: bool,
+        context: ActionContext<'a>,
         action: &'a PluginAction,
         in_files: &'a Vec<String>,
         out_files: &'a Vec<String>,
-        _working_dir: &'a String,
-        maybe_replacements: Option<&'a HashMap<String, String>>,
     ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error>>> + Send + 'a>> {
+        let ActionContext { step_id, emakefile_cwd, maybe_replacements, .. } = context;
         Box::pin(async move {
             let copy_action = match action {
                 PluginAction::Copy { copy } => copy,
@@ -213,7 +209,10 @@ impl Action for Copy {
     fn clone_box(&self) -> Box<dyn Action + Send + Sync> {
         Box::new(Self)
     }
-    fn get_checksum(&self) -> Option<String> {
+    fn get_checksum<'a>(&'a self, _action: &'a PluginAction) -> Option<String> {
+        None
+    }
+    fn get_clean<'a>(&'a self, _action: &'a PluginAction) -> Option<String> {
         None
     }
 }
@@ -1,7 +1,6 @@
 use config_macros::ActionDoc;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
     fs,
     future::Future,
     io::BufReader,
@@ -20,7 +19,7 @@ use std::io;
 use tar::Archive as TarArchive;
 use xz2::read::XzDecoder;
 
-use super::Action;
+use super::{Action, ActionContext};
 pub static ID: &str = "extract";
 
 #[derive(ActionDoc, Debug, Clone, Serialize, Deserialize)]
@@ -180,16 +179,12 @@ impl Action for Extract {
 
     fn run<'a>(
         &'a self,
-        target_id: &'a str,
-        step_id: &'a str,
-        _emakefile_cwd: &'a str,
-        _silent: bool,
+        context: ActionContext<'a>,
         _action: &'a PluginAction,
         in_files: &'a Vec<String>,
         out_files: &'a Vec<String>,
-        _working_dir: &'a String,
-        _maybe_replacements: Option<&'a HashMap<String, String>>,
     ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error>>> + Send + 'a>> {
+        let ActionContext { target_id, step_id, .. } = context;
         Box::pin(async move {
             let from = &in_files[0];
             let to = &out_files[0];
@@ -219,7 +214,10 @@ impl Action for Extract {
     fn clone_box(&self) -> Box<dyn Action + Send + Sync> {
         Box::new(Self)
     }
-    fn get_checksum(&self) -> Option<String> {
+    fn get_checksum<'a>(&'a self, _action: &'a PluginAction) -> Option<String> {
+        None
+    }
+    fn get_clean<'a>(&'a self, _action: &'a PluginAction) -> Option<String> {
         None
     }
 }
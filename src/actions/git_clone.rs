@@ -15,10 +15,10 @@ use std::{
 use crate::{
     console::log,
     emake::{self, loader::TargetType, InFile, PluginAction},
-    CREDENTIALS_STORE,
+    utils, CREDENTIALS_STORE,
 };
 
-use super::Action;
+use super::{Action, ActionContext};
 pub static ID: &str = "git_clone";
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -292,16 +292,12 @@ impl Action for GitClone {
 
     fn run<'a>(
         &'a self,
-        _target_id: &'a str,
-        step_id: &'a str,
-        emakefile_cwd: &'a str,
-        _silent: bool,
+        context: ActionContext<'a>,
         action: &'a PluginAction,
         in_files: &'a Vec<String>,
         out_files: &'a Vec<String>,
-        _working_dir: &'a String,
-        maybe_replacements: Option<&'a HashMap<String, String>>,
     ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error>>> + Send + 'a>> {
+        let ActionContext { step_id, emakefile_cwd, maybe_replacements, .. } = context;
         Box::pin(async move {
             let git_action = match action {
                 PluginAction::GitClone { git_clone } => git_clone.clone(),
@@ -411,7 +407,8 @@ impl Action for GitClone {
                             );
                         }
 
-                        true
+                        // Stop the clone of a step which timed out
+                        !utils::is_step_cancelled(&step_id)
                     });
 
                     fetch_callbacks = add_credentials(
@@ -455,7 +452,10 @@ impl Action for GitClone {
     fn clone_box(&self) -> Box<dyn Action + Send + Sync> {
         Box::new(Self)
     }
-    fn get_checksum(&self) -> Option<String> {
+    fn get_checksum<'a>(&'a self, _action: &'a PluginAction) -> Option<String> {
+        None
+    }
+    fn get_clean<'a>(&'a self, _action: &'a PluginAction) -> Option<String> {
         None
     }
 }
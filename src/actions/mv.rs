@@ -5,7 +5,6 @@ use fs_extra::{
 };
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
     fs,
     future::Future,
     path::{Path, PathBuf},
@@ -17,7 +16,7 @@ use crate::{
     emake::{InFile, PluginAction},
 };
 
-use super::Action;
+use super::{Action, ActionContext};
 pub static ID: &str = "move";
 
 #[derive(ActionDoc, Debug, Clone, Serialize, Deserialize)]
@@ -98,16 +97,12 @@ impl Action for Move {
 
     fn run<'a>(
         &'a self,
-        _target_id: &'a str,
-        step_id: &'a str,
-        _emakefile_cwd: &'a str,
-        _silent: bool,
+        context: ActionContext<'a>,
         _action: &'a PluginAction,
         in_files: &'a Vec<String>,
         out_files: &'a Vec<String>,
-        _working_dir: &'a String,
-        _maybe_replacements: Option<&'a HashMap<String, String>>,
     ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error>>> + Send + 'a>> {
+        let ActionContext { step_id, .. } = context;
         Box::pin(async move {
             let mut src = in_files.clone();
             let destination = out_files[0].clone();
@@ -183,7 +178,10 @@ impl Action for Move {
     fn clone_box(&self) -> Box<dyn Action + Send + Sync> {
         Box::new(Self)
     }
-    fn get_checksum(&self) -> Option<String> {
+    fn get_checksum<'a>(&'a self, _action: &'a PluginAction) -> Option<String> {
+        None
+    }
+    fn get_clean<'a>(&'a self, _action: &'a PluginAction) -> Option<String> {
         None
     }
 }
@@ -1,12 +1,12 @@
 use config_macros::ActionDoc;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, future::Future, pin::Pin};
+use std::{future::Future, pin::Pin};
 
 use crate::{
     emake::{InFile, PluginAction},
 };
 
-use super::Action;
+use super::{Action, ActionContext};
 pub static ID: &str = "remove";
 
 #[derive(ActionDoc, Debug, Clone, Serialize, Deserialize)]
@@ -69,15 +69,10 @@ impl Action for Remove {
 
     fn run<'a>(
         &'a self,
-        _target_id: &'a str,
-        _step_id: &'a str,
-        _emakefile_cwd: &'a str,
-        _silent: bool,
+        _context: ActionContext<'a>,
         _action: &'a PluginAction,
         in_files: &'a Vec<String>,
         _out_files: &'a Vec<String>,
-        _working_dir: &'a String,
-        _maybe_replacements: Option<&'a HashMap<String, String>>,
     ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error>>> + Send + 'a>> {
         Box::pin(async move {
             let paths = in_files.clone();
@@ -100,7 +95,10 @@ impl Action for Remove {
     fn clone_box(&self) -> Box<dyn Action + Send + Sync> {
         Box::new(Self)
     }
-    fn get_checksum(&self) -> Option<String> {
+    fn get_checksum<'a>(&'a self, _action: &'a PluginAction) -> Option<String> {
+        None
+    }
+    fn get_clean<'a>(&'a self, _action: &'a PluginAction) -> Option<String> {
         None
     }
 }
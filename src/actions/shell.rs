@@ -1,21 +1,30 @@
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     future::Future,
     io::{BufRead, BufReader},
+    path::{Path, PathBuf},
     pin::Pin,
     process::{Command, Stdio},
 };
 
 use crate::{
-    console::log,
+    console::{
+        events::{self, Event},
+        log,
+    },
     emake::{self, InFile, PluginAction},
-    get_cwd,
+    cache::{self, state::{self, ImplicitFiles}},
+    depfile, get_cwd,
+    sandbox::Sandbox,
+    tracer::{self, FileAccesses},
+    utils::{self, get_absolute_file_path},
 };
 use config_macros::ActionDoc;
 
-use super::Action;
+use super::{Action, ActionContext, ActionError};
 pub static ID: &str = "shell";
+const STDERR_TAIL_LINES: usize = 10;
 
 #[derive(ActionDoc, Debug, Clone, Serialize, Deserialize)]
 #[action_doc(
@@ -50,16 +59,82 @@ pub struct ShellAction {
     pub in_files: Option<Vec<InFile>>,
     #[action_prop(description = "Specify the list of files generated by the shell command.", required: false)]
     pub out_files: Option<Vec<String>>,
-    #[action_prop(description = "Specify a command to compute a checksum of the output. Usefull when your command don't generate output files.", required: false)]
+    #[action_prop(description = "Specify a command to compute a checksum of the output. The step is executed again when the checksum changes. Usefull when your command don't generate output files.", required: false)]
     pub checksum: Option<String>,
     #[action_prop(description = "Current working directory to execute the command", required: false)]
     pub cwd: Option<String>,
-    #[action_prop(description = "[NOT IMPLEMENTED YET] Specify a command to clean outputs.", required: false)]
+    #[action_prop(description = "Specify a command to clean outputs, used by `emake clean [target]`. By default the out_files are deleted.", required: false)]
     pub clean: Option<String>,
+    #[action_prop(description = "Run the command in a sandbox containing only the in_files, only the out_files are copied back. Linux only.", required: false)]
+    pub sandbox: Option<bool>,
+    #[action_prop(description = "Trace the files opened by the command, the undeclared files read are checked on the next build and the undeclared files written are reported. Linux x86_64 only.", required: false)]
+    pub track_deps: Option<bool>,
+    #[action_prop(description = "Makefile-style dependency file written by the command, like the `.d` file of `gcc -MD`. Its prerequisites are checked on the next build.", required: false)]
+    pub depfile: Option<String>,
 }
 
 pub struct Shell;
 
+fn is_declared(path: &Path, declared_files: &[String]) -> bool {
+    declared_files
+        .iter()
+        .any(|file| path.starts_with(get_absolute_file_path(file)))
+}
+
+/// Store the undeclared files of the project used by the command, they are checked on the next build
+fn record_implicit_files(step_id: &str, file_accesses: &FileAccesses, in_files: &[String], out_files: &[String]) {
+    let project_dir = get_cwd();
+    let ignored_dirs = [
+        cache::get_state_dir_path(),
+        cache::get_logs_dir_path(),
+        cache::get_sandbox_dir_path(),
+    ];
+    let is_project_file = |path: &PathBuf| {
+        path.starts_with(&project_dir) && !ignored_dirs.iter().any(|dir| path.starts_with(dir)) && path.is_file()
+    };
+
+    let implicit_out_files: Vec<String> = file_accesses
+        .written
+        .iter()
+        .filter(|path| is_project_file(path) && !is_declared(path, out_files))
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    // A file written then read by the command is one of its outputs
+    let implicit_in_files: Vec<String> = file_accesses
+        .read
+        .iter()
+        .filter(|path| {
+            is_project_file(path)
+                && !file_accesses.written.contains(*path)
+                && !is_declared(path, in_files)
+                && !is_declared(path, out_files)
+        })
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+
+    if !implicit_out_files.is_empty() {
+        log::warning!(
+            "The step {} wrote files which are not declared in its out_files: {}",
+            step_id,
+            implicit_out_files.join(", ")
+        );
+    }
+    log::debug!(
+        "Files used by the step {} without being declared: in {:?}, out {:?}",
+        step_id,
+        implicit_in_files,
+        implicit_out_files
+    );
+
+    state::set_implicit_files(
+        step_id,
+        ImplicitFiles {
+            in_files: implicit_in_files,
+            out_files: implicit_out_files,
+        },
+    );
+}
+
 impl Action for Shell {
     fn insert_in_files<'a>(
         &'a self,
@@ -101,18 +176,42 @@ impl Action for Shell {
 
     fn run<'a>(
         &'a self,
-        _target_id: &'a str,
-        step_id: &'a str,
-        emakefile_cwd: &'a str,
-        _silent: bool,
+        context: ActionContext<'a>,
         action: &'a PluginAction,
         in_files: &'a Vec<String>,
         out_files: &'a Vec<String>,
-        _working_dir: &'a String,
-        maybe_replacements: Option<&'a HashMap<String, String>>,
     ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error>>> + Send + 'a>> {
+        let ActionContext { step_id, emakefile_cwd, maybe_replacements, env, .. } = context;
         Box::pin(async move {
             if let PluginAction::Shell { shell } = action {
+                let mut maybe_sandbox = None;
+                let project_in_files = in_files;
+                let project_out_files = out_files;
+                let mut in_files = project_in_files.clone();
+                let mut out_files = project_out_files.clone();
+                let mut defaults = maybe_replacements.cloned().unwrap_or_default();
+                if shell.sandbox.unwrap_or(false) {
+                    let sandbox = Sandbox::create(step_id, &in_files)?;
+                    sandbox.prepare_out_files(&out_files)?;
+                    // The command sees the files of the project at their place in the sandbox
+                    in_files = in_files.iter().map(|file| sandbox.map_path(file)).collect();
+                    out_files = out_files.iter().map(|file| sandbox.map_path(file)).collect();
+                    // Only the EMAKE_* folders are paths, the params and items of the step are kept as written
+                    for (_name, value) in defaults.iter_mut().filter(|(name, _value)| name.starts_with("EMAKE_")) {
+                        *value = sandbox.map_path(value);
+                    }
+                    maybe_sandbox = Some(sandbox);
+                }
+
+                let mut track_deps = shell.track_deps.unwrap_or(false);
+                if track_deps && !tracer::is_supported() {
+                    log::warning!(
+                        "The files used by the step {} are not tracked, track_deps is only supported on Linux x86_64",
+                        step_id
+                    );
+                    track_deps = false;
+                }
+
                 let mut command = shell.cmd.clone();
                 let in_files_string = in_files.join(" ");
                 let out_files_string = out_files.join(" ");
@@ -131,9 +230,7 @@ impl Action for Shell {
                     replacements.insert(format!("out_files[{}]", i), f.clone());
                 }
 
-                if let Some(defaults) = maybe_replacements {
-                    replacements.extend(defaults.clone());
-                }
+                replacements.extend(defaults);
 
                 command = emake::compiler::compile(
                     &command,
@@ -142,7 +239,10 @@ impl Action for Shell {
                     None,
                 );
 
-                let cwd_as_string = get_cwd().to_string_lossy().to_string();
+                let mut cwd_as_string = get_cwd().to_string_lossy().to_string();
+                if let Some(sandbox) = &maybe_sandbox {
+                    cwd_as_string = sandbox.map_path(&cwd_as_string);
+                }
                 let current_working_directory_for_command = emake::compiler::compile(
                     shell.cwd.as_ref().unwrap_or(&cwd_as_string),
                     &emakefile_cwd.to_string(),
@@ -150,6 +250,11 @@ impl Action for Shell {
                     None,
                 );
 
+                let maybe_depfile_path = shell.depfile.as_ref().map(|depfile| {
+                    let depfile = emake::compiler::compile(depfile, emakefile_cwd, Some(&replacements), None);
+                    Path::new(&current_working_directory_for_command).join(depfile)
+                });
+
                 let (shell, arg) = if cfg!(windows) {
                     ("cmd", "/C")
                 } else {
@@ -160,75 +265,146 @@ impl Action for Shell {
                     "Current workingdirectory to execute the command is {}",
                     current_working_directory_for_command
                 );
+                log::debug!("Environment of the command: {}", env.describe());
 
                 let current_working_directory_for_command_clone = current_working_directory_for_command.clone();
                 let arg_clone = String::from(arg);
                 let command_clone = command.clone();
                 let step_id_clone = String::from(step_id);
+                let env_clone = env.clone();
 
-                let spawn_result: Result<(), String>= tokio::task::spawn_blocking(move || {
-                    let child_result = Command::new(shell)
+                let spawn_result: Result<Option<FileAccesses>, ActionError> = tokio::task::spawn_blocking(move || {
+                    let mut process = Command::new(shell);
+                    process
                         .current_dir(current_working_directory_for_command_clone)
                         .arg(arg_clone)
                         .arg(&command_clone)
                         .stdout(Stdio::piped())
-                        .stderr(Stdio::piped())
-                        .spawn();
+                        .stderr(Stdio::piped());
+                    env_clone.apply(&mut process);
+                    utils::set_own_process_group(&mut process);
+                    if track_deps {
+                        tracer::prepare(&mut process);
+                    }
+                    let child_result = process.spawn();
                     
                     if child_result.is_err() {
-                        return Err(child_result.err().unwrap().to_string());
+                        return Err(ActionError {
+                            message: child_result.err().unwrap().to_string(),
+                            exit_code: None,
+                            stderr: Vec::new(),
+                        });
                     }
 
                     let mut child = child_result.unwrap();
+                    utils::register_process(&step_id_clone, child.id());
 
                     let stdout = child.stdout.take().unwrap();
                     let stderr = child.stderr.take().unwrap();
 
                     let stdout_reader = BufReader::new(stdout);
                     let stderr_reader = BufReader::new(stderr);
-                    let cmd_stdout = command_clone.clone();
+                    let cmd_stdout = env_clone.mask(&command_clone);
 
                     log::action_info!(step_id_clone, self::ID, "[command] {}", cmd_stdout);
 
                     let stderr_step_id_clone = String::from(&step_id_clone);
-                    let _ = std::thread::spawn(move || {
+                    let stdout_env = env_clone.clone();
+                    let stdout_thread = std::thread::spawn(move || {
                         for line in stdout_reader.lines() {
                             if let Ok(text) = line {
+                                let text = stdout_env.mask(&text);
                                 let output = format!("[stdout] {}", text);
                                 log::action_info!(stderr_step_id_clone, ID, "{}", output);
+                                events::emit(Event::Output {
+                                    step_id: &stderr_step_id_clone,
+                                    stream: "stdout",
+                                    line: &text,
+                                });
                             }
                         }
                     });
 
                     let stderr_step_id_clone = String::from(&step_id_clone);
-                    let _ = std::thread::spawn(move || {
+                    let stderr_env = env_clone.clone();
+                    let stderr_thread = std::thread::spawn(move || {
+                        let mut stderr_tail = VecDeque::new();
                         for line in stderr_reader.lines() {
                             if let Ok(text) = line {
+                                let text = stderr_env.mask(&text);
                                 let output = format!("[stderr] {}", text);
                                 log::action_info!(stderr_step_id_clone, ID, "{}", output);
+                                events::emit(Event::Output {
+                                    step_id: &stderr_step_id_clone,
+                                    stream: "stderr",
+                                    line: &text,
+                                });
+                                if stderr_tail.len() == STDERR_TAIL_LINES {
+                                    stderr_tail.pop_front();
+                                }
+                                stderr_tail.push_back(text);
                             }
                         }
+                        stderr_tail
                     });
 
-                    let status = child.wait().expect("Failed to wait on child");
+                    let (status, maybe_file_accesses) = match track_deps {
+                        true => {
+                            let (status, file_accesses) = tracer::trace(&mut child);
+                            (status, Some(file_accesses))
+                        }
+                        false => (child.wait().expect("Failed to wait on child"), None),
+                    };
+                    utils::unregister_process(child.id());
+
+                    // Read the whole output before the step is reported as done
+                    let _ = stdout_thread.join();
+                    let stderr_tail = stderr_thread.join().unwrap_or_default();
 
                     if !status.success() {
-                        return Err(format!(
-                            "Command `{}` failed with exit code {}.",
-                            command_clone,
-                            status.code().unwrap_or(-1)
-                        ));
+                        return Err(ActionError {
+                            message: format!(
+                                "Command `{}` failed with exit code {}.",
+                                cmd_stdout,
+                                status.code().unwrap_or(-1)
+                            ),
+                            exit_code: status.code(),
+                            stderr: stderr_tail.into_iter().collect(),
+                        });
                     }
 
-                    Ok(())
+                    Ok(maybe_file_accesses)
                 })
                 .await
                 .unwrap();
 
-                if spawn_result.is_err() {
-                    let error_message = spawn_result.err().unwrap().to_string();
-                    let error: Result<(), Box<dyn std::error::Error>> = Err(error_message.into());
-                    return error;
+                let maybe_file_accesses = match spawn_result {
+                    Ok(maybe_file_accesses) => maybe_file_accesses,
+                    Err(mut error) => {
+                        if let Some(explanation) = maybe_sandbox.as_ref().and_then(|sandbox| sandbox.explain_failure(&error.stderr)) {
+                            error.message = format!("{} {}", error.message, explanation);
+                        }
+                        return Err(error.into());
+                    }
+                };
+
+                if maybe_file_accesses.is_some() || maybe_depfile_path.is_some() {
+                    let mut file_accesses = maybe_file_accesses.unwrap_or_default();
+                    if let Some(depfile_path) = &maybe_depfile_path {
+                        let prerequisites = depfile::read(depfile_path, Path::new(&current_working_directory_for_command))
+                            .map_err(|error| format!("The step {} didn't write its depfile: {}", step_id, error))?;
+                        file_accesses.read.extend(prerequisites);
+                        file_accesses.written.remove(depfile_path);
+                    }
+                    if let Some(sandbox) = &maybe_sandbox {
+                        file_accesses.read = file_accesses.read.iter().map(|path| sandbox.unmap_path(path)).collect();
+                        file_accesses.written = file_accesses.written.iter().map(|path| sandbox.unmap_path(path)).collect();
+                    }
+                    record_implicit_files(step_id, &file_accesses, project_in_files, project_out_files);
+                }
+
+                if let Some(sandbox) = &maybe_sandbox {
+                    sandbox.collect_out_files(project_out_files)?;
                 }
             }
 
@@ -239,7 +415,16 @@ impl Action for Shell {
     fn clone_box(&self) -> Box<dyn Action + Send + Sync> {
         Box::new(Self)
     }
-    fn get_checksum(&self) -> Option<String> {
-        None
+    fn get_checksum<'a>(&'a self, action: &'a PluginAction) -> Option<String> {
+        match action {
+            PluginAction::Shell { shell } => shell.checksum.clone(),
+            _ => None,
+        }
+    }
+    fn get_clean<'a>(&'a self, action: &'a PluginAction) -> Option<String> {
+        match action {
+            PluginAction::Shell { shell } => shell.clean.clone(),
+            _ => None,
+        }
     }
 }
@@ -13,7 +13,7 @@ use crate::{
     emake::{self, InFile, PluginAction},
 };
 
-use super::Action;
+use super::{Action, ActionContext};
 pub static ID: &str = "yaml";
 
 #[derive(ActionDoc, Debug, Clone, Serialize, Deserialize)]
@@ -135,16 +135,12 @@ impl Action for Yaml {
 
     fn run<'a>(
         &'a self,
-        _target_id: &'a str,
-        _step_id: &'a str,
-        emakefile_cwd: &'a str,
-        _silent: bool,
+        context: ActionContext<'a>,
         action: &'a PluginAction,
         in_files: &'a Vec<String>,
         out_files: &'a Vec<String>,
-        _working_dir: &'a String,
-        maybe_replacements: Option<&'a HashMap<String, String>>,
     ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error>>> + Send + 'a>> {
+        let ActionContext { emakefile_cwd, maybe_replacements, .. } = context;
         Box::pin(async move {
             match action {
                 PluginAction::Yaml { yaml } => {
@@ -190,7 +186,10 @@ impl Action for Yaml {
     fn clone_box(&self) -> Box<dyn Action + Send + Sync> {
         Box::new(Self)
     }
-    fn get_checksum(&self) -> Option<String> {
+    fn get_checksum<'a>(&'a self, _action: &'a PluginAction) -> Option<String> {
+        None
+    }
+    fn get_clean<'a>(&'a self, _action: &'a PluginAction) -> Option<String> {
         None
     }
 }
@@ -1,13 +1,18 @@
 use crate::{
-    console::log, get_cwd, graph::runner::is_url, utils::get_absolute_file_path,
-    CACHE_IN_FILE_TO_UPDATE, CACHE_OUT_FILE_TO_UPDATE,
+    console::log, emake::ChangeDetection, get_cwd, graph::runner::is_url,
+    utils::get_absolute_file_path, CACHE_IN_FILE_TO_UPDATE, CACHE_OUT_FILE_TO_UPDATE,
 };
-use std::{fs, path::Path, time::SystemTime};
+use std::{fs, io, path::Path, time::SystemTime};
 
-const CACHE_DIR: &str = ".emake/cache";
+mod legacy;
+pub mod state;
+
+const EMAKE_DIR: &str = ".emake";
+const STATE_DIR: &str = ".emake/state";
 const WORKING_DIR: &str = ".emake/workspace";
+const SANDBOX_DIR: &str = ".emake/workspace/.sandbox";
 const OUT_DIR: &str = ".emake/out";
-const FOOTPRINTS_DIR: &str = ".emake/footprints";
+const LOGS_DIR: &str = ".emake/logs";
 
 async fn create_dir(dir: &str) {
     let cache_dir = get_dir_path(dir);
@@ -20,15 +25,15 @@ async fn create_dir(dir: &str) {
 }
 
 pub async fn create_cache_dir() {
-    create_dir(CACHE_DIR).await;
+    create_dir(STATE_DIR).await;
     create_dir(WORKING_DIR).await;
     create_dir(OUT_DIR).await;
-    create_dir(FOOTPRINTS_DIR).await;
+    legacy::migrate(Path::new(&get_dir_path(EMAKE_DIR)));
 }
 
 pub fn write_cache(ignore_not_exists: &bool) {
     // Now remove and collect owned JoinHandles
-    let cache_in_file_to_update: Vec<(String, String)> = CACHE_IN_FILE_TO_UPDATE
+    let cache_in_file_to_update: Vec<(String, String, ChangeDetection)> = CACHE_IN_FILE_TO_UPDATE
         .iter()
         .map(|entry| entry.key().clone())
         .collect::<Vec<_>>();
@@ -39,7 +44,7 @@ pub fn write_cache(ignore_not_exists: &bool) {
         &Vec::from([String::from("in_file")]),
     );
 
-    let cache_out_file_to_update: Vec<(String, String)> = CACHE_OUT_FILE_TO_UPDATE
+    let cache_out_file_to_update: Vec<(String, String, ChangeDetection)> = CACHE_OUT_FILE_TO_UPDATE
         .iter()
         .map(|entry| entry.key().clone())
         .collect::<Vec<_>>();
@@ -49,21 +54,48 @@ pub fn write_cache(ignore_not_exists: &bool) {
         &ignore_not_exists,
         &Vec::from([String::from("out_file")]),
     );
+
+    CACHE_IN_FILE_TO_UPDATE.clear();
+    CACHE_OUT_FILE_TO_UPDATE.clear();
+    state::commit();
 }
 
-fn write_file_cache(files: &Vec<(String, String)>, ignore_not_exists: &bool, tags: &Vec<String>) {
-    for (file_absolute_path, action_id) in files {
+fn write_file_cache(
+    files: &Vec<(String, String, ChangeDetection)>,
+    ignore_not_exists: &bool,
+    tags: &Vec<String>,
+) {
+    for (file_absolute_path, action_id, change_detection) in files {
         if let Ok(file_exists) = std::fs::exists(&file_absolute_path) {
             if file_exists {
-                if has_file_changed(file_absolute_path, action_id, ignore_not_exists) {
-                    let maybe_current_time = get_file_modification_time(&file_absolute_path);
-                    if let Some(current_time) = maybe_current_time {
-                        write_file_in_cache(file_absolute_path, action_id, &current_time, tags);
+                let maybe_current_time = get_file_modification_time(&file_absolute_path);
+                if let Some(current_time) = maybe_current_time {
+                    let maybe_cache_entry = get_file_cache_entry(file_absolute_path, action_id);
+                    let need_hash = *change_detection == ChangeDetection::Hash;
+                    let up_to_date = match &maybe_cache_entry {
+                        Some((previous_time, previous_hash)) => {
+                            *previous_time == current_time && (!need_hash || previous_hash.is_some())
+                        }
+                        None => false,
+                    };
+
+                    if !up_to_date {
+                        let mut maybe_hash = None;
+                        if need_hash {
+                            maybe_hash = get_file_hash(file_absolute_path);
+                        }
+                        write_file_in_cache(
+                            file_absolute_path,
+                            action_id,
+                            &current_time,
+                            maybe_hash.as_deref(),
+                            tags,
+                        );
                     }
                 }
             } else {
                 let current_time = format!("{:?}", SystemTime::now()).replace(" ", "");
-                write_file_in_cache(file_absolute_path, action_id, &current_time, tags);
+                write_file_in_cache(file_absolute_path, action_id, &current_time, None, tags);
 
                 if !ignore_not_exists {
                     log::panic!("You try to cache a file that doesn't exist. Check your input/output, the file is {}", file_absolute_path);
@@ -121,129 +153,86 @@ fn get_file_modification_time(file_absolute_path: &str) -> Option<String> {
     None
 }
 
-fn write_file_in_cache(
-    file_absolute_path: &str,
-    action_id: &str,
-    modification_date: &str,
-    tags: &Vec<String>,
-) {
-    let cache_file_path = get_file_cache(&file_absolute_path);
-    let cache_file_dir = cache_file_path.parent().unwrap();
-    if let Ok(cache_file_dir_exists) = std::fs::exists(&cache_file_dir) {
-        if !cache_file_dir_exists {
-            // println!("Exists dir cache {:?}", cache_file_dir);
-            std::fs::create_dir_all(&cache_file_dir).unwrap();
-        }
-
-        // println!("Write file cache {:?}", cache_file_path);
-        let glob_paths =
-            glob::glob(&format!("{}/tag_*", cache_file_dir.to_str().unwrap())).unwrap();
-        for path in glob_paths {
-            if let Ok(p) = path {
-                fs::remove_file(p).unwrap();
+/// Compute a blake3 digest of a file. Directories are hashed recursively,
+/// in the same way `latest_mtime` walks them, using sorted entry names so the
+/// digest doesn't depend on the file system listing order.
+pub fn get_file_hash(file_absolute_path: &str) -> Option<String> {
+    fn hash_path(path: &Path, hasher: &mut blake3::Hasher) -> Option<()> {
+        let metadata = fs::metadata(path).ok()?;
+
+        if metadata.is_dir() {
+            let mut entries: Vec<_> = fs::read_dir(path)
+                .ok()?
+                .filter_map(|entry| entry.ok())
+                .collect();
+            entries.sort_by_key(|entry| entry.file_name());
+
+            for entry in entries {
+                hasher.update(entry.file_name().to_string_lossy().as_bytes());
+                hasher.update(&[0]);
+                hash_path(&entry.path(), hasher)?;
             }
+        } else {
+            let mut file = fs::File::open(path).ok()?;
+            io::copy(&mut file, hasher).ok()?;
         }
 
-        for tag in tags {
-            fs::write(cache_file_dir.join(format!("tag_{}", tag)), "").unwrap();
-        }
-
-        // Check if the line already exist
-        let mut action_line = String::from(action_id);
-        action_line.push_str(" ");
-        action_line.push_str(&modification_date);
-
-        if let Ok(cache_file_exists) = std::fs::exists(&cache_file_path) {
-            if cache_file_exists {
-                let file_content = std::fs::read_to_string(&cache_file_path).unwrap();
-                let mut lines: Vec<&str> = file_content.split("\n").collect();
-                let mut maybe_line_action_id_index = None;
-
-                for (line_index, line) in lines.iter().enumerate() {
-                    let details: Vec<&str> = line.split(" ").collect();
-                    let line_action_id = details[0];
+        Some(())
+    }
 
-                    if line_action_id == action_id {
-                        maybe_line_action_id_index = Some(line_index);
-                        break;
-                    }
-                }
+    let mut hasher = blake3::Hasher::new();
+    hash_path(Path::new(file_absolute_path), &mut hasher)?;
+    Some(hasher.finalize().to_hex().to_string())
+}
 
-                if let Some(line_action_id_index) = maybe_line_action_id_index {
-                    lines[line_action_id_index] = action_line.as_str();
-                } else {
-                    lines.push(&action_line.as_str());
-                }
+/// Read the cached modification time and optional hash of a file for an action
+fn get_file_cache_entry(file_absolute_path: &str, action_id: &str) -> Option<(String, Option<String>)> {
+    state::get_file_entry(file_absolute_path, action_id)
+        .map(|entry| (entry.modification_time, entry.hash))
+}
 
-                std::fs::write(&cache_file_path, &lines.join("\n")).unwrap();
-                return ();
-            }
+fn write_file_in_cache(
+    file_absolute_path: &str,
+    action_id: &str,
+    modification_date: &str,
+    maybe_hash: Option<&str>,
+    tags: &Vec<String>,
+) {
+    // A file can be both an in_file and an out_file of the same action
+    let mut all_tags = state::get_file_entry(file_absolute_path, action_id)
+        .map(|entry| entry.tags)
+        .unwrap_or_default();
+    for tag in tags {
+        if !all_tags.contains(tag) {
+            all_tags.push(tag.clone());
         }
-
-        std::fs::write(&cache_file_path, &action_line).unwrap();
     }
+
+    state::set_file_entry(
+        file_absolute_path,
+        action_id,
+        state::FileEntry {
+            modification_time: String::from(modification_date),
+            hash: maybe_hash.map(String::from),
+            tags: all_tags,
+        },
+    );
 }
 
 pub async fn get_cache_action_checksum(action_id: &str) -> Option<String> {
-    let cache_dir = get_cache_dir_path();
-    let checksum_cache_path = Path::new(&cache_dir).join("checksum");
-
-    if let Ok(checksum_cache_exists) = tokio::fs::try_exists(&checksum_cache_path).await {
-        if checksum_cache_exists {
-            if let Ok(cache_content) = tokio::fs::read_to_string(&checksum_cache_path).await {
-                let lines: Vec<&str> = cache_content.split("\n").collect();
-                for line in lines {
-                    let details: Vec<&str> = line.split(" ").collect();
-                    let line_action_id = details[0];
-                    if action_id == line_action_id {
-                        let line_checksum = details[1];
-                        return Some(String::from(line_checksum));
-                    }
-                }
-            }
-        }
-    }
-
-    None
+    state::get_checksum(action_id)
 }
 
 pub async fn write_cache_action_checksum(action_id: &str, checksum: &str) {
-    let cache_dir = get_cache_dir_path();
-    let checksum_cache_path = Path::new(&cache_dir).join("checksum");
-
-    if let Ok(checksum_cache_exists) = tokio::fs::try_exists(&checksum_cache_path).await {
-        if !checksum_cache_exists {
-            tokio::fs::File::create(&checksum_cache_path).await.unwrap();
-        }
-    }
-
-    if let Ok(cache_content) = tokio::fs::read_to_string(&checksum_cache_path).await {
-        let mut maybe_action_line_index = None;
-        let mut lines: Vec<&str> = cache_content.split("\n").collect();
-        for (line_index, line) in lines.iter().enumerate() {
-            let details: Vec<&str> = line.split(" ").collect();
-            let line_action_id = details[0];
-            if action_id == line_action_id {
-                maybe_action_line_index = Some(line_index);
-                break;
-            }
-        }
-
-        let formated_line = format!("{} {}", action_id, checksum);
-        if let Some(action_line_index) = maybe_action_line_index {
-            lines[action_line_index] = formated_line.as_str();
-        } else {
-            lines.push(formated_line.as_str());
-        }
-
-        let content = lines.join("\n");
-        tokio::fs::write(&checksum_cache_path, &content)
-            .await
-            .unwrap();
-    }
+    state::set_checksum(action_id, checksum);
 }
 
-pub fn has_file_changed(file: &str, action_id: &str, ignore_not_exists: &bool) -> bool {
+pub fn has_file_changed(
+    file: &str,
+    action_id: &str,
+    ignore_not_exists: &bool,
+    change_detection: &ChangeDetection,
+) -> bool {
     let mut filename = String::from(file);
     
     if is_url(file) {
@@ -255,22 +244,16 @@ pub fn has_file_changed(file: &str, action_id: &str, ignore_not_exists: &bool) -
 
     if let Some(modification_date) = get_file_modification_time(&file_absolute_path) {
         file_changed = true;
-        let cache_file = get_file_cache(&file_absolute_path);
-        if let Ok(cache_file_exists) = std::fs::exists(&cache_file) {
-            if cache_file_exists {
-                let file_content = std::fs::read_to_string(&cache_file).unwrap();
-                let lines: Vec<&str> = file_content.split("\n").collect();
-
-                for line in lines {
-                    let details: Vec<&str> = line.split(" ").collect();
-                    let line_action_id = details[0];
-
-                    if line_action_id == action_id {
-                        let previous_time = String::from(details[1]);
-                        if previous_time == modification_date {
-                            file_changed = false;
-                        }
-                        break;
+
+        if let Some((previous_time, maybe_previous_hash)) = get_file_cache_entry(&file_absolute_path, action_id) {
+            if previous_time == modification_date {
+                file_changed = false;
+            } else if *change_detection == ChangeDetection::Hash {
+                // The modification time is only a fast path, compare the content before rerunning
+                if let Some(previous_hash) = maybe_previous_hash {
+                    if get_file_hash(&file_absolute_path) == Some(previous_hash) {
+                        log::trace!("File {} has a new modification time but the same content", file_absolute_path);
+                        file_changed = false;
                     }
                 }
             }
@@ -280,26 +263,28 @@ pub fn has_file_changed(file: &str, action_id: &str, ignore_not_exists: &bool) -
     file_changed
 }
 
-fn get_file_cache(file_absolute_path: &str) -> std::path::PathBuf {
-    let cache_path = format!("{}{}/time", get_cache_dir_path(), file_absolute_path);
-    let path = std::path::Path::new(&cache_path);
-    path.to_path_buf()
+pub fn get_emake_dir_path() -> String {
+    get_dir_path(EMAKE_DIR)
 }
 
-pub fn get_cache_dir_path() -> String {
-    get_dir_path(CACHE_DIR)
+pub fn get_state_dir_path() -> String {
+    get_dir_path(STATE_DIR)
 }
 
 pub fn get_working_dir_path() -> String {
     get_dir_path(WORKING_DIR)
 }
 
+pub fn get_sandbox_dir_path() -> String {
+    get_dir_path(SANDBOX_DIR)
+}
+
 pub fn get_out_dir_path() -> String {
     get_dir_path(OUT_DIR)
 }
 
-pub fn get_footprints_dir_path() -> String {
-    get_dir_path(FOOTPRINTS_DIR)
+pub fn get_logs_dir_path() -> String {
+    get_dir_path(LOGS_DIR)
 }
 
 fn get_dir_path(dir: &str) -> String {
@@ -0,0 +1,116 @@
+use std::{fs, path::Path};
+
+use walkdir::WalkDir;
+
+use crate::{
+    cache::state::{self, FileEntry},
+    console::log,
+};
+
+// Layout used before the state store:
+// - .emake/cache/<absolute file path>/time with `action_id modification_time [hash]` lines
+// - .emake/cache/<absolute file path>/tag_<tag> marker files
+// - .emake/cache/checksum with `action_id checksum` lines
+// - .emake/footprints/<step path> containing the footprint
+const LEGACY_CACHE_DIR: &str = "cache";
+const LEGACY_FOOTPRINTS_DIR: &str = "footprints";
+
+fn migrate_files(cache_dir: &Path) {
+    for entry in WalkDir::new(cache_dir).into_iter().filter_map(|entry| entry.ok()) {
+        if !entry.file_type().is_file() || entry.file_name() != "time" {
+            continue;
+        }
+
+        let file_dir = entry.path().parent().unwrap();
+        let Ok(relative_path) = file_dir.strip_prefix(cache_dir) else {
+            continue;
+        };
+        let file_absolute_path = format!("/{}", relative_path.to_string_lossy());
+
+        let mut tags = Vec::new();
+        if let Ok(read_dir) = fs::read_dir(file_dir) {
+            for tag_entry in read_dir.filter_map(|tag_entry| tag_entry.ok()) {
+                let tag_filename = tag_entry.file_name().to_string_lossy().to_string();
+                if let Some(tag) = tag_filename.strip_prefix("tag_") {
+                    tags.push(String::from(tag));
+                }
+            }
+        }
+
+        let content = fs::read_to_string(entry.path()).unwrap_or_default();
+        for line in content.split("\n") {
+            let details: Vec<&str> = line.split(" ").collect();
+            if details.len() < 2 {
+                continue;
+            }
+
+            state::set_file_entry(
+                &file_absolute_path,
+                details[0],
+                FileEntry {
+                    modification_time: String::from(details[1]),
+                    hash: details.get(2).map(|hash| String::from(*hash)),
+                    tags: tags.clone(),
+                },
+            );
+        }
+    }
+
+    let checksum_content = fs::read_to_string(cache_dir.join("checksum")).unwrap_or_default();
+    for line in checksum_content.split("\n") {
+        let details: Vec<&str> = line.split(" ").collect();
+        if details.len() >= 2 {
+            state::set_checksum(details[0], details[1]);
+        }
+    }
+}
+
+fn migrate_footprints(footprints_dir: &Path) {
+    for entry in WalkDir::new(footprints_dir).into_iter().filter_map(|entry| entry.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let Ok(relative_path) = entry.path().strip_prefix(footprints_dir) else {
+            continue;
+        };
+        let step_id = format!(
+            "//{}",
+            relative_path
+                .to_string_lossy()
+                .replace("_targets_/", "targets:")
+        );
+
+        if let Ok(footprint) = fs::read_to_string(entry.path()) {
+            state::set_footprint(&step_id, &footprint);
+        }
+    }
+}
+
+/// Import a `.emake` folder created by a previous version of emake inside the state store
+pub fn migrate(emake_dir: &Path) {
+    let cache_dir = emake_dir.join(LEGACY_CACHE_DIR);
+    let footprints_dir = emake_dir.join(LEGACY_FOOTPRINTS_DIR);
+
+    if !cache_dir.exists() && !footprints_dir.exists() {
+        return;
+    }
+
+    log::info!("Migrating the emake cache to the new state format");
+
+    if cache_dir.exists() {
+        migrate_files(&cache_dir);
+    }
+
+    if footprints_dir.exists() {
+        migrate_footprints(&footprints_dir);
+    }
+
+    state::commit();
+
+    for legacy_dir in [cache_dir, footprints_dir] {
+        if legacy_dir.exists() {
+            fs::remove_dir_all(&legacy_dir).unwrap();
+        }
+    }
+}
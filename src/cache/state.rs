@@ -0,0 +1,413 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{cache::get_state_dir_path, console::log};
+
+// The state store is an append-only log of committed records on top of a compacted index.
+// Readers load the index then replay the log, a batch of records is only applied once its
+// commit marker has been written. Both files carry a generation number so a reader never
+// mixes an index with the log of another generation. A compaction writes the index before the log,
+// a log older than the index has already been applied to it and is discarded.
+const INDEX_FILE: &str = "index.json";
+const LOG_FILE: &str = "log.jsonl";
+const LOCK_FILE: &str = "lock";
+const FORMAT_VERSION: u32 = 1;
+/// Size of the log in bytes above which it is compacted into the index
+const COMPACT_THRESHOLD: u64 = 1024 * 1024;
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+const STALE_LOCK_AGE: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub modification_time: String,
+    pub hash: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// Files used by a step without being declared, discovered when the step ran
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImplicitFiles {
+    pub in_files: Vec<String>,
+    pub out_files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
+    pub generation: u64,
+    /// File path => action id => entry
+    #[serde(default)]
+    pub files: HashMap<String, HashMap<String, FileEntry>>,
+    #[serde(default)]
+    pub footprints: HashMap<String, String>,
+    #[serde(default)]
+    pub checksums: HashMap<String, String>,
+    #[serde(default)]
+    pub implicit_files: HashMap<String, ImplicitFiles>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Record {
+    Header {
+        generation: u64,
+    },
+    File {
+        path: String,
+        action_id: String,
+        entry: FileEntry,
+    },
+    Footprint {
+        step_id: String,
+        footprint: String,
+    },
+    Checksum {
+        action_id: String,
+        checksum: String,
+    },
+    ImplicitFiles {
+        step_id: String,
+        files: ImplicitFiles,
+    },
+    /// Forget everything known about an action
+    Invalidate {
+        action_id: String,
+    },
+    Commit,
+}
+
+impl State {
+    fn apply(&mut self, record: &Record) {
+        match record {
+            Record::File {
+                path,
+                action_id,
+                entry,
+            } => {
+                self.files
+                    .entry(path.clone())
+                    .or_default()
+                    .insert(action_id.clone(), entry.clone());
+            }
+            Record::Footprint { step_id, footprint } => {
+                self.footprints.insert(step_id.clone(), footprint.clone());
+            }
+            Record::Checksum {
+                action_id,
+                checksum,
+            } => {
+                self.checksums.insert(action_id.clone(), checksum.clone());
+            }
+            Record::ImplicitFiles { step_id, files } => {
+                self.implicit_files.insert(step_id.clone(), files.clone());
+            }
+            Record::Invalidate { action_id } => {
+                for actions in self.files.values_mut() {
+                    actions.remove(action_id);
+                }
+                self.files.retain(|_, actions| !actions.is_empty());
+                self.footprints.remove(action_id);
+                self.checksums.remove(action_id);
+                self.implicit_files.remove(action_id);
+            }
+            Record::Header { .. } | Record::Commit => {}
+        }
+    }
+}
+
+static STATE: Lazy<RwLock<State>> = Lazy::new(|| RwLock::new(load(Path::new(&get_state_dir_path()))));
+static PENDING: Lazy<Mutex<Vec<Record>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+struct StateLock {
+    path: PathBuf,
+}
+
+impl StateLock {
+    fn acquire(state_dir: &Path) -> StateLock {
+        let path = state_dir.join(LOCK_FILE);
+        let start = SystemTime::now();
+
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let _ = write!(file, "{}", std::process::id());
+                    return StateLock { path };
+                }
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                    let is_stale = fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok())
+                        .map(|age| age > STALE_LOCK_AGE)
+                        .unwrap_or(false);
+
+                    if is_stale {
+                        log::warning!("Removing stale state lock {}", path.to_string_lossy());
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+
+                    if start.elapsed().unwrap_or_default() > LOCK_TIMEOUT {
+                        log::panic!(
+                            "Can't lock the emake state {}, is another emake process running ?",
+                            path.to_string_lossy()
+                        );
+                    }
+
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(error) => {
+                    log::panic!(
+                        "Can't lock the emake state {}: {}",
+                        path.to_string_lossy(),
+                        error
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Drop for StateLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn read_index(state_dir: &Path) -> State {
+    match fs::read_to_string(state_dir.join(INDEX_FILE)) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|error| {
+            log::panic!(
+                "The emake state index {} is corrupted: {}",
+                state_dir.join(INDEX_FILE).to_string_lossy(),
+                error
+            );
+        }),
+        Err(_) => State {
+            version: FORMAT_VERSION,
+            ..State::default()
+        },
+    }
+}
+
+/// Generation written in the header of the log, None when there is no readable header
+fn read_log_generation(state_dir: &Path) -> Option<u64> {
+    let file = fs::File::open(state_dir.join(LOG_FILE)).ok()?;
+    let line = BufReader::new(file).lines().next()?.ok()?;
+    match serde_json::from_str::<Record>(&line) {
+        Ok(Record::Header { generation }) => Some(generation),
+        _ => None,
+    }
+}
+
+/// Read the committed records of the log.
+/// Returns None when the log belongs to a newer generation than the expected one.
+fn read_log(state_dir: &Path, generation: u64) -> Option<Vec<Record>> {
+    let Ok(file) = fs::File::open(state_dir.join(LOG_FILE)) else {
+        return Some(Vec::new());
+    };
+
+    let mut committed = Vec::new();
+    let mut batch = Vec::new();
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let Ok(line) = line else { break };
+        if line.is_empty() {
+            continue;
+        }
+
+        let Ok(record) = serde_json::from_str::<Record>(&line) else {
+            // A torn write, everything after is not committed
+            log::debug!("Ignoring malformed state record at line {}", index + 1);
+            break;
+        };
+
+        match record {
+            Record::Header {
+                generation: log_generation,
+            } => {
+                if log_generation > generation {
+                    return None;
+                }
+                if log_generation < generation {
+                    // The compaction stopped after writing the index, the log is already in it
+                    log::debug!("Ignoring the state log of the generation {}, already compacted", log_generation);
+                    return Some(Vec::new());
+                }
+            }
+            Record::Commit => committed.append(&mut batch),
+            record => batch.push(record),
+        }
+    }
+
+    Some(committed)
+}
+
+fn load(state_dir: &Path) -> State {
+    for _ in 0..50 {
+        let mut state = read_index(state_dir);
+
+        if let Some(records) = read_log(state_dir, state.generation) {
+            for record in &records {
+                state.apply(record);
+            }
+            return state;
+        }
+
+        // A compaction happened between the index and the log reading
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    log::panic!(
+        "Can't load the emake state {}, the index and the log don't match",
+        state_dir.to_string_lossy()
+    );
+}
+
+fn write_atomically(path: &Path, content: &[u8]) {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp_path).unwrap();
+    file.write_all(content).unwrap();
+    file.sync_all().unwrap();
+    fs::rename(&tmp_path, path).unwrap();
+}
+
+fn to_line(record: &Record) -> String {
+    let mut line = serde_json::to_string(record).unwrap();
+    line.push('\n');
+    line
+}
+
+fn compact(state_dir: &Path) {
+    let mut state = load(state_dir);
+    state.version = FORMAT_VERSION;
+    state.generation += 1;
+
+    write_atomically(
+        &state_dir.join(INDEX_FILE),
+        serde_json::to_string(&state).unwrap().as_bytes(),
+    );
+    write_atomically(
+        &state_dir.join(LOG_FILE),
+        to_line(&Record::Header {
+            generation: state.generation,
+        })
+        .as_bytes(),
+    );
+    log::debug!("Emake state compacted to generation {}", state.generation);
+}
+
+fn stage(record: Record) {
+    STATE.write().unwrap().apply(&record);
+    PENDING.lock().unwrap().push(record);
+}
+
+/// Write all staged records in one transaction
+pub fn commit() {
+    let records: Vec<Record> = std::mem::take(&mut *PENDING.lock().unwrap());
+    if records.is_empty() {
+        return;
+    }
+
+    let state_dir = PathBuf::from(get_state_dir_path());
+    fs::create_dir_all(&state_dir).unwrap();
+    let _lock = StateLock::acquire(&state_dir);
+
+    let log_path = state_dir.join(LOG_FILE);
+    let generation = read_index(&state_dir).generation;
+    if read_log_generation(&state_dir).is_none_or(|log_generation| log_generation < generation) {
+        // No log yet, or the log of an interrupted compaction which is already in the index
+        write_atomically(&log_path, to_line(&Record::Header { generation }).as_bytes());
+    }
+
+    let mut content = String::new();
+    for record in &records {
+        content.push_str(&to_line(record));
+    }
+    content.push_str(&to_line(&Record::Commit));
+
+    let mut log_file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .unwrap();
+    log_file.write_all(content.as_bytes()).unwrap();
+    log_file.sync_all().unwrap();
+
+    let log_size = log_file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+    if log_size > COMPACT_THRESHOLD {
+        compact(&state_dir);
+    }
+}
+
+pub fn get_file_entry(path: &str, action_id: &str) -> Option<FileEntry> {
+    STATE
+        .read()
+        .unwrap()
+        .files
+        .get(path)
+        .and_then(|actions| actions.get(action_id))
+        .cloned()
+}
+
+pub fn set_file_entry(path: &str, action_id: &str, entry: FileEntry) {
+    stage(Record::File {
+        path: String::from(path),
+        action_id: String::from(action_id),
+        entry,
+    });
+}
+
+pub fn get_files() -> HashMap<String, HashMap<String, FileEntry>> {
+    STATE.read().unwrap().files.clone()
+}
+
+pub fn get_footprint(step_id: &str) -> Option<String> {
+    STATE.read().unwrap().footprints.get(step_id).cloned()
+}
+
+pub fn set_footprint(step_id: &str, footprint: &str) {
+    stage(Record::Footprint {
+        step_id: String::from(step_id),
+        footprint: String::from(footprint),
+    });
+}
+
+pub fn get_checksum(action_id: &str) -> Option<String> {
+    STATE.read().unwrap().checksums.get(action_id).cloned()
+}
+
+pub fn set_checksum(action_id: &str, checksum: &str) {
+    stage(Record::Checksum {
+        action_id: String::from(action_id),
+        checksum: String::from(checksum),
+    });
+}
+
+pub fn invalidate(action_id: &str) {
+    stage(Record::Invalidate {
+        action_id: String::from(action_id),
+    });
+}
+
+pub fn get_implicit_files(step_id: &str) -> ImplicitFiles {
+    STATE.read().unwrap().implicit_files.get(step_id).cloned().unwrap_or_default()
+}
+
+pub fn set_implicit_files(step_id: &str, files: ImplicitFiles) {
+    stage(Record::ImplicitFiles {
+        step_id: String::from(step_id),
+        files,
+    });
+}
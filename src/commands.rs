@@ -7,6 +7,11 @@ pub mod clean;
 pub mod graph;
 pub mod doc;
 pub mod keyring;
+pub mod list;
+pub mod logs;
+pub mod query;
+#[cfg(target_os = "linux")]
+pub mod watch;
 pub mod init;
 
 pub async fn run_command(matches: &ArgMatches) {
@@ -16,15 +21,65 @@ pub async fn run_command(matches: &ArgMatches) {
     if let Some(_) = matches.subcommand_matches("init") {
         init::initialize();
     } else if let Some(matches) = matches.subcommand_matches("build") {
-        let target = matches.get_one::<String>("target").expect("required");
-        build::run(target, true).await;
+        let targets: Vec<String> = matches
+            .get_many::<String>("targets")
+            .map(|targets| targets.cloned().collect())
+            .unwrap_or_default();
+        if let Some(jobs) = matches.get_one::<usize>("jobs") {
+            crate::graph::scheduler::set_jobs(*jobs);
+        }
+        crate::graph::runner::set_keep_going(matches.get_flag("keep-going"));
+        if let Some(events_path) = matches.get_one::<String>("events") {
+            crate::console::events::set_output(events_path);
+        }
+        if let Some(profile_path) = matches.get_one::<String>("profile") {
+            crate::graph::profiler::set_profile(profile_path);
+        }
+        let params: Vec<String> = matches
+            .get_many::<String>("param")
+            .map(|params| params.cloned().collect())
+            .unwrap_or_default();
+        crate::emake::params::set_cli_params(&params);
+        for report in matches.get_many::<String>("report").into_iter().flatten() {
+            crate::report::add_report(report);
+        }
+        crate::graph::runner::set_dry_run(matches.get_flag("dry-run"), matches.get_flag("explain"));
+        build::run(&targets, true).await;
+    } else if let Some(matches) = matches.subcommand_matches("watch") {
+        let targets: Vec<String> = matches
+            .get_many::<String>("targets")
+            .map(|targets| targets.cloned().collect())
+            .unwrap_or_default();
+        #[cfg(target_os = "linux")]
+        watch::run(&targets).await;
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = targets;
+            crate::console::log::panic!("The watch command is only available on Linux");
+        }
     } else if let Some(matches) = matches.subcommand_matches("clean") {
         let dry_run = matches.get_flag("dry_run");
-        clean::run(&dry_run).await;
+        let maybe_target = matches.get_one::<String>("target");
+        clean::run(maybe_target, &dry_run).await;
     } else if let Some(matches) = matches.subcommand_matches("graph") {
         let target = matches.get_one::<String>("target").expect("required");
         let path_png = matches.get_one::<String>("path").expect("required");
         graph::run(target, path_png);
+    } else if let Some(matches) = matches.subcommand_matches("list") {
+        let format = matches.get_one::<String>("format").expect("default");
+        list::run(format);
+    } else if let Some(matches) = matches.subcommand_matches("logs") {
+        let target = matches.get_one::<String>("target").expect("required");
+        let step = matches.get_one::<String>("step");
+        logs::run(target, step, matches.get_flag("follow")).await;
+    } else if let Some(matches) = matches.subcommand_matches("query") {
+        for kind in ["deps", "rdeps"] {
+            if let Some(submatches) = matches.subcommand_matches(kind) {
+                let target = submatches.get_one::<String>("target").expect("required");
+                let format = submatches.get_one::<String>("format").expect("default");
+                query::run(kind, target, format);
+            }
+        }
     } else if let Some(_matches) = matches.subcommand_matches("doc") {
         doc::generate();
     } else if let Some(matches) = matches.subcommand_matches("keyring") {
@@ -3,7 +3,7 @@ use std::{
 };
 
 use crate::{
-    cache, console::{log, progress_bar::{self, set_loader_message}}, get_cwd, graph::{self, generator::get_absolute_target_path}, utils::format_elapsed
+    cache, console::{events::{self, Event}, log, progress_bar::{self, set_loader_message}}, get_cwd, emake, report, graph::{self, generator::expand_target_path, runner::StepFailure}, utils::{self, format_elapsed}
 };
 use crossbeam_channel::{bounded, Receiver};
 
@@ -18,23 +18,84 @@ fn ctrl_channel() -> Result<Receiver<()>, ctrlc::Error> {
     Ok(receiver)
 }
 
-pub async fn run(target: &String, _find_root: bool) {
+pub fn handle_ctrl_c() {
     let ctrl_c_events = ctrl_channel().unwrap();
 
     // Spawn ctrl+c handler in background thread
     let _ = thread::spawn(move || {
         ctrl_c_events.recv().unwrap();
         log::warning!("Receive CTRL-C signal from user");
+        // Steps run in their own process group, they don't receive the signal of the terminal
+        utils::kill_running_processes();
         exit(1, None);
     });
+}
+
+pub async fn run(targets: &[String], _find_root: bool) {
+    handle_ctrl_c();
 
     // run the main async task
     let start = Instant::now();
-    main_task(target).await;
+    let target_paths = resolve_targets(targets);
+    events::emit(Event::BuildStarted { targets: &target_paths });
+    let build_graph = graph::dag::load(&target_paths);
+    let result = graph::runner::run_graph(build_graph).await;
+
+    let failed_steps = graph::runner::get_failed_steps();
+    if result.is_err() || !failed_steps.is_empty() {
+        print_failure_summary(&failed_steps);
+        exit(1, Some(start.elapsed()));
+    }
 
     exit(0, Some(start.elapsed()));
 }
 
+pub fn print_failure_summary(failed_steps: &Vec<StepFailure>) {
+    if failed_steps.is_empty() {
+        return;
+    }
+
+    let step_id_width = failed_steps
+        .iter()
+        .map(|failure| failure.step_id.len())
+        .max()
+        .unwrap_or(0)
+        .max("STEP".len());
+    let description_width = failed_steps
+        .iter()
+        .map(|failure| failure.description.len())
+        .max()
+        .unwrap_or(0)
+        .max("DESCRIPTION".len());
+
+    log::info!(
+        "\x1b[1;91m{} failed {}\x1b[0m",
+        failed_steps.len(),
+        if failed_steps.len() > 1 { "steps" } else { "step" }
+    );
+    log::info!(
+        "\x1b[1m{:<step_id_width$}  {:<description_width$}  EXIT CODE\x1b[0m",
+        "STEP",
+        "DESCRIPTION"
+    );
+
+    for failure in failed_steps {
+        let exit_code = failure
+            .exit_code
+            .map(|exit_code| exit_code.to_string())
+            .unwrap_or(String::from("-"));
+        log::info!(
+            "{:<step_id_width$}  {:<description_width$}  {}",
+            failure.step_id,
+            failure.description,
+            exit_code
+        );
+        for line in &failure.stderr {
+            log::info!("    \x1b[90m{}\x1b[0m", line);
+        }
+    }
+}
+
 pub fn update_progress(increment_running: bool, increment_done: bool) {
     let mut running_steps = RUNNING_STEPS.load(Ordering::Relaxed);
     let mut done_steps = DONE_STEPS.load(Ordering::Relaxed);
@@ -59,18 +120,52 @@ pub fn update_progress(increment_running: bool, increment_done: bool) {
     set_loader_message(&format!("Building [{} | {}]", running_step_text, done_step_text));
 }
 
-async fn main_task(target: &String) {
+pub fn reset_progress() {
+    RUNNING_STEPS.store(0, Ordering::Relaxed);
+    DONE_STEPS.store(0, Ordering::Relaxed);
+}
+
+/// Absolute paths of the targets to build, from the command line or the default targets
+pub fn resolve_targets(targets: &[String]) -> Vec<String> {
     let mut build_file = PathBuf::from(get_cwd());
     build_file.push("Emakefile");
+    let build_file_path = build_file.to_str().unwrap().to_string();
+
+    let mut targets = targets.to_vec();
+    if targets.is_empty() {
+        if build_file.exists() {
+            let emakefile = emake::loader::load_file(&build_file_path);
+            if let Some(default_targets) = emakefile.default {
+                targets = default_targets.to_vec();
+            }
+        }
+
+        if targets.is_empty() {
+            log::panic!("No target to build, give a target or set the default key in the root Emakefile");
+        }
+    }
+
+    let mut target_paths = Vec::new();
+    for target in &targets {
+        let expanded_target_paths = expand_target_path(target, &build_file_path);
+        if expanded_target_paths.is_empty() {
+            log::panic!("No target matches the pattern {}", target);
+        }
 
-    let target_path = get_absolute_target_path(
-        target,
-        &build_file.to_str().unwrap().to_string());
+        for target_path in expanded_target_paths {
+            if !target_paths.contains(&target_path) {
+                target_paths.push(target_path);
+            }
+        }
+    }
 
-    graph::runner::run_target(target_path).await;
+    target_paths
 }
 
 pub fn exit(code: i32, duration: Option<std::time::Duration>) {
+    report::write_reports();
+    graph::profiler::write_profile();
+
     if code == 0 {
         if duration.is_some() {
             let elapsed = format_elapsed(duration.unwrap());
@@ -78,8 +173,14 @@ pub fn exit(code: i32, duration: Option<std::time::Duration>) {
         } else {
             log::success!("Build successfully done");
         }
+    } else if let Some(duration) = duration {
+        log::error!("Build failed after {}", format_elapsed(duration));
     }
 
+    events::emit(Event::BuildFinished {
+        success: code == 0,
+        duration_ms: duration.map(|duration| duration.as_millis()),
+    });
     cache::write_cache(&(code != 0));
     progress_bar::finish();
     std::process::exit(code);
@@ -1,14 +1,129 @@
-use crate::{console::log::{self, StepStatus}, get_cwd};
+use std::path::Path;
+
+use crate::{
+    cache::state,
+    console::log::{self, StepStatus},
+    emake::{self, env::EnvConfig, Step},
+    get_cwd,
+    graph::{
+        generator::get_absolute_target_path,
+        runner::{get_real_out_files, get_step_replacements},
+    },
+    utils, ACTIONS_STORE,
+};
 
 const CACHE_DIR: &str = ".emake";
 
-pub async fn run(dry_run: &bool) {
+/// Out files recorded in cache for a step, ignoring those that are also in_files of the step
+fn get_step_out_files(step_id: &str) -> Vec<String> {
+    let out_file_tag = String::from("out_file");
+    let in_file_tag = String::from("in_file");
+    let mut out_files = Vec::new();
+
+    for (file, actions) in state::get_files() {
+        if let Some(entry) = actions.get(step_id) {
+            if entry.tags.contains(&out_file_tag)
+                && !entry.tags.contains(&in_file_tag)
+                && std::fs::exists(&file).unwrap_or(false)
+            {
+                out_files.push(file);
+            }
+        }
+    }
+
+    out_files.sort();
+    out_files
+}
+
+async fn run_target(target: &String, dry_run: &bool) {
+    let build_file = get_cwd().join("Emakefile");
+    let target_path = get_absolute_target_path(target, &build_file.to_string_lossy().to_string());
+    let build_graph = crate::graph::dag::load(&Vec::from([target_path.clone()]));
+
+    // The steps of the target and of its dependencies, with the for_each and matrices expanded.
+    // A step only depends on the steps placed before it, they are cleaned the other way round so
+    // the outputs of a step are removed before the outputs of the steps it reads, it may have moved them.
+    let steps: Vec<(String, Step, String, EnvConfig)> = build_graph
+        .steps
+        .into_iter()
+        .rev()
+        .map(|node| (node.id, node.step, node.emakefile_path, node.env))
+        .collect();
+
+    if *dry_run {
+        log::info!("List of steps to clean:");
+    }
+
+    for (step_id, step, emakefile_path, env) in &steps {
+        let Some(plugin) = ACTIONS_STORE.get(&step.action) else {
+            log::panic!(
+                "Can't clean step \"{}\", we are not able to find the plugin used in this step",
+                step.description
+            );
+        };
+
+        if let Some(clean_command) = plugin.get_clean(&step.action) {
+            let out_files = get_real_out_files(step_id, step, emakefile_path).await;
+            let mut replacements = get_step_replacements(step, emakefile_path);
+            replacements.insert(String::from("out_files"), out_files.join(" "));
+            for (i, f) in out_files.iter().enumerate() {
+                replacements.insert(format!("out_files[{}]", i), f.clone());
+            }
+
+            if *dry_run {
+                let compiled_command = emake::compiler::compile(&clean_command, emakefile_path, Some(&replacements), None);
+                log::info!("    [{}] run {}", step_id, compiled_command);
+                continue;
+            }
+
+            log::step_info!(step_id, StepStatus::Running, format!("Cleaning {}", step.description));
+            let step_env = env.resolve(emakefile_path, &replacements);
+            let (status, _stdout, stderr) = utils::run_command(&clean_command, Path::new(emakefile_path), Some(&replacements), &step_env);
+            if !status.success() {
+                log::panic!(
+                    "An error occured when cleaning the step [{}] {}: {}",
+                    step_id,
+                    step.description,
+                    stderr
+                );
+            }
+        } else {
+            let out_files = get_step_out_files(step_id);
+
+            if *dry_run {
+                for file in &out_files {
+                    log::info!("    [{}] delete {}", step_id, file);
+                }
+                continue;
+            }
+
+            if !out_files.is_empty() {
+                log::step_info!(step_id, StepStatus::Running, format!("Removing files of {}", step.description));
+                fs_extra::remove_items(&out_files).unwrap();
+            }
+        }
+
+        state::invalidate(step_id);
+        log::step_info!(step_id, StepStatus::Finished, format!("Cleaned {}", step.description));
+    }
+
+    if !dry_run {
+        state::commit();
+        log::success!("Target {} cleaned successfully !", target_path);
+    } else {
+        log::warning!("Dry run mode nothing was deleted");
+    }
+}
+
+pub async fn run(maybe_target: Option<&String>, dry_run: &bool) {
+    if let Some(target) = maybe_target {
+        run_target(target, dry_run).await;
+        return;
+    }
+
     // let clean_commands = graph::analysor::get_clean_commands(cwd);
     let path = get_cwd().join(CACHE_DIR);
 
-    // Getting out_files from cache
-    let cache_folder = path.join("cache");
-
     if *dry_run {
         log::info!("List of files to delete:");
     }
@@ -19,19 +134,26 @@ pub async fn run(dry_run: &bool) {
         files_to_delete.push(path.to_string_lossy().to_string());
     }
 
-    for out_file_result in glob::glob(&format!("{}/**/tag_out_file", cache_folder.to_str().unwrap())).unwrap()
-    {
-        if let Ok(out_file) = out_file_result {
-            let dirname = out_file.parent().unwrap();
-            // Exclude outfile that are also in_file for the same target
-            let has_in_file = dirname.join("tag_in_file").exists();
-            if has_in_file {
-                log::debug!("Ignoring file because it's also an in_file {:?}", out_file);
-                continue;
-            }
+    // Getting out_files from cache
+    for (file, actions) in state::get_files() {
+        let out_file_tag = String::from("out_file");
+        let in_file_tag = String::from("in_file");
+        let is_out_file = actions.values().any(|entry| entry.tags.contains(&out_file_tag));
+        // Exclude outfile that are also in_file for the same target
+        let has_in_file = actions
+            .values()
+            .any(|entry| entry.tags.contains(&out_file_tag) && entry.tags.contains(&in_file_tag));
+        if !is_out_file {
+            continue;
+        }
+
+        if has_in_file {
+            log::debug!("Ignoring file because it's also an in_file {:?}", file);
+            continue;
+        }
 
-            let file_to_delete = dirname.to_string_lossy().replacen(cache_folder.to_str().unwrap(), "", 1);
-            files_to_delete.push(file_to_delete);
+        if std::fs::exists(&file).unwrap_or(false) {
+            files_to_delete.push(file);
         }
     }
 
@@ -14,6 +14,7 @@ use crate::{
 pub fn run(target: &String, path: &String) {
     let build_file = get_cwd().join("Emakefile");
     let target_path = get_absolute_target_path(target, &build_file.to_str().unwrap().to_string());
+    crate::graph::dag::load(&Vec::from([target_path.clone()]));
     let graphviz = graph::viewer::as_graphviz(&target_path);
     let g: Graph = parse(&graphviz).unwrap();
     let mut graphviz_path = PathBuf::from(path);
@@ -0,0 +1,110 @@
+use serde::Serialize;
+
+use crate::{
+    emake,
+    get_cwd,
+    graph::generator::{find_emakefiles, get_absolute_target_path, to_absolute_entity_path},
+};
+
+#[derive(Serialize)]
+struct TargetDescription {
+    path: String,
+    description: Option<String>,
+    deps: Vec<String>,
+    steps: usize,
+}
+
+#[derive(Serialize, Default)]
+struct Project {
+    targets: Vec<TargetDescription>,
+    variables: Vec<String>,
+    secrets: Vec<String>,
+}
+
+fn sorted_keys<V>(map: &Option<std::collections::HashMap<String, V>>) -> Vec<String> {
+    let mut keys: Vec<String> = map
+        .as_ref()
+        .map(|map| map.keys().cloned().collect())
+        .unwrap_or_default();
+    keys.sort();
+    keys
+}
+
+fn discover() -> Project {
+    let mut project = Project::default();
+
+    for emakefile_path in find_emakefiles(&get_cwd()) {
+        let emakefile_path = emakefile_path.to_string_lossy().to_string();
+        let emakefile = emake::loader::load_file(&emakefile_path);
+
+        let mut target_names: Vec<&String> = emakefile.targets.keys().collect();
+        target_names.sort();
+        for target_name in target_names {
+            let target = &emakefile.targets[target_name];
+            project.targets.push(TargetDescription {
+                path: to_absolute_entity_path(&emakefile_path, "targets", target_name),
+                description: target.description.clone(),
+                deps: target
+                    .deps
+                    .iter()
+                    .flatten()
+                    .map(|dep| get_absolute_target_path(dep, &emakefile_path))
+                    .collect(),
+                steps: target.steps.as_ref().map(|steps| steps.len()).unwrap_or(0),
+            });
+        }
+
+        for variable_name in sorted_keys(&emakefile.variables) {
+            project
+                .variables
+                .push(to_absolute_entity_path(&emakefile_path, "variables", &variable_name));
+        }
+
+        for secret_name in sorted_keys(&emakefile.secrets) {
+            project
+                .secrets
+                .push(to_absolute_entity_path(&emakefile_path, "secrets", &secret_name));
+        }
+    }
+
+    project
+}
+
+pub fn run(format: &String) {
+    let project = discover();
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&project).unwrap());
+        return;
+    }
+
+    println!("Targets");
+    for target in &project.targets {
+        println!(
+            "  {} ({} {})",
+            target.path,
+            target.steps,
+            if target.steps != 1 { "steps" } else { "step" }
+        );
+        if let Some(description) = &target.description {
+            println!("      {}", description);
+        }
+        if !target.deps.is_empty() {
+            println!("      deps: {}", target.deps.join(", "));
+        }
+    }
+
+    if !project.variables.is_empty() {
+        println!("Variables");
+        for variable in &project.variables {
+            println!("  {}", variable);
+        }
+    }
+
+    if !project.secrets.is_empty() {
+        println!("Secrets");
+        for secret in &project.secrets {
+            println!("  {}", secret);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use walkdir::WalkDir;
+
+use crate::{console::log, get_cwd, graph::generator::get_absolute_target_path, step_logs};
+
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Steps of the target having logs, with the folder of their logs
+fn find_steps_logs(target_path: &str, step: Option<&String>) -> Vec<(String, PathBuf)> {
+    let target_logs_dir = step_logs::get_logs_dir(target_path);
+    let mut steps = Vec::new();
+
+    for entry in WalkDir::new(&target_logs_dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_dir())
+    {
+        if step_logs::get_runs(entry.path()).is_empty() {
+            continue;
+        }
+
+        let relative_path = entry.path().strip_prefix(&target_logs_dir).unwrap();
+        let step_path: Vec<String> = relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().to_string())
+            .collect();
+        if step.is_some_and(|step| step_path.first() != Some(step)) {
+            continue;
+        }
+
+        steps.push((format!("{}/{}", target_path, step_path.join("/")), entry.path().to_path_buf()));
+    }
+
+    // Sort steps by index, not alphabetically
+    steps.sort_by_key(|(step_id, _logs_dir)| {
+        step_id
+            .trim_start_matches(target_path)
+            .split('/')
+            .map(|part| (part.parse::<usize>().unwrap_or(usize::MAX), part.to_string()))
+            .collect::<Vec<(usize, String)>>()
+    });
+    steps
+}
+
+/// Read the lines added to a file since the last read, a line being written is kept for the next read
+fn read_new_lines(path: &PathBuf, offset: &mut u64) -> Vec<String> {
+    let Ok(mut file) = File::open(path) else {
+        return Vec::new();
+    };
+    if file.seek(SeekFrom::Start(*offset)).is_err() {
+        return Vec::new();
+    }
+
+    let mut content = Vec::new();
+    if file.read_to_end(&mut content).is_err() {
+        return Vec::new();
+    }
+    let Some(last_line_end) = content.iter().rposition(|byte| *byte == b'\n') else {
+        return Vec::new();
+    };
+
+    *offset += last_line_end as u64 + 1;
+    String::from_utf8_lossy(&content[..last_line_end])
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+fn print_header(step_id: &str, log_path: &Path) {
+    println!("==> {} ({}) <==", step_id, log_path.to_string_lossy());
+}
+
+pub async fn run(target: &String, step: Option<&String>, follow: bool) {
+    let build_file = get_cwd().join("Emakefile");
+    let target_path = get_absolute_target_path(target, &build_file.to_string_lossy().to_string());
+
+    let steps = find_steps_logs(&target_path, step);
+    if steps.is_empty() && !follow {
+        match step {
+            Some(step) => {
+                log::panic!("No logs found for the step {} of the target {}", step, target_path);
+            }
+            None => {
+                log::panic!("No logs found for the target {}, its steps have not run yet", target_path);
+            }
+        }
+    }
+
+    if !follow {
+        for (step_id, logs_dir) in steps {
+            let log_path = step_logs::get_runs(&logs_dir).pop().unwrap();
+            print_header(&step_id, &log_path);
+            for line in read_new_lines(&log_path, &mut 0) {
+                println!("{}", line);
+            }
+        }
+        return;
+    }
+
+    // Print the lines of the last run of each step as they are written, until ctrl-c
+    let mut followed: HashMap<String, (PathBuf, u64)> = HashMap::new();
+    let mut last_step_printed = None;
+    loop {
+        for (step_id, logs_dir) in find_steps_logs(&target_path, step) {
+            let Some(log_path) = step_logs::get_runs(&logs_dir).pop() else {
+                continue;
+            };
+
+            let (followed_path, offset) = followed
+                .entry(step_id.clone())
+                .or_insert((log_path.clone(), 0));
+            if *followed_path != log_path {
+                // A new run of the step started
+                *followed_path = log_path.clone();
+                *offset = 0;
+                last_step_printed = None;
+            }
+
+            let lines = read_new_lines(&log_path, offset);
+            if lines.is_empty() {
+                continue;
+            }
+
+            if last_step_printed.as_ref() != Some(&step_id) {
+                print_header(&step_id, &log_path);
+                last_step_printed = Some(step_id.clone());
+            }
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+
+        tokio::time::sleep(FOLLOW_INTERVAL).await;
+    }
+}
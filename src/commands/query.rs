@@ -0,0 +1,105 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    console::log,
+    emake::{self, loader::extract_info_from_path, params},
+    get_cwd,
+    graph::{
+        self,
+        generator::{find_emakefiles, get_absolute_target_path, to_absolute_entity_path, to_emakefile_path},
+    },
+};
+
+fn check_target_exists(target_path: &str) {
+    let Ok((target_path, _params)) = params::split_target_path(target_path) else {
+        log::panic!("Invalid target path: {}", target_path);
+    };
+    let target_path = target_path.as_str();
+    let emakefile_path = to_emakefile_path(target_path).to_string_lossy().to_string();
+    let target_name = extract_info_from_path(target_path, &emakefile_path)
+        .map(|target_info| target_info.target_name)
+        .unwrap_or_default();
+
+    if !std::path::Path::new(&emakefile_path).exists()
+        || !emake::loader::load_file(&emakefile_path).targets.contains_key(&target_name)
+    {
+        log::panic!("Target not found: {}", target_path);
+    }
+}
+
+/// Targets reachable from the given ones following the edges, sorted
+fn walk(edges: &HashMap<&str, Vec<&str>>, target_paths: &[&str]) -> Vec<String> {
+    let mut reached = BTreeSet::new();
+    let mut to_visit = target_paths.to_vec();
+    while let Some(current) = to_visit.pop() {
+        for next in edges.get(current).into_iter().flatten() {
+            if !target_paths.contains(next) && reached.insert(next.to_string()) {
+                to_visit.push(next);
+            }
+        }
+    }
+    reached.into_iter().collect()
+}
+
+/// All the targets the target depends on, directly or not
+fn get_deps(target_path: &str) -> Vec<String> {
+    let graph = graph::dag::load(&Vec::from([String::from(target_path)]));
+    let edges: HashMap<&str, Vec<&str>> = graph
+        .targets
+        .iter()
+        .map(|(path, target)| (path.as_str(), target.deps.iter().map(String::as_str).collect()))
+        .collect();
+
+    walk(&edges, &[params::canonical_target_path(target_path).as_str()])
+}
+
+/// All the targets of the project depending on the target, directly or not
+fn get_rdeps(target_path: &str) -> Vec<String> {
+    let mut project_targets = Vec::new();
+    for emakefile_path in find_emakefiles(&get_cwd()) {
+        let emakefile_path = emakefile_path.to_string_lossy().to_string();
+        let emakefile = emake::loader::load_file(&emakefile_path);
+        for target_name in emakefile.targets.keys() {
+            project_targets.push(to_absolute_entity_path(&emakefile_path, "targets", target_name));
+        }
+    }
+
+    // A single graph for the whole project, its edges are reversed once
+    let graph = graph::dag::load(&project_targets);
+    let mut reverse_edges: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (path, target) in &graph.targets {
+        for dep in &target.deps {
+            reverse_edges.entry(dep.as_str()).or_default().push(path.as_str());
+        }
+    }
+
+    // A matrix target without all its parameters stands for its combinations
+    let target_path = params::canonical_target_path(target_path);
+    let mut target_paths = vec![target_path.as_str()];
+    if let (Some(target), Ok((base_path, _params))) = (graph.targets.get(&target_path), params::split_target_path(&target_path)) {
+        let combinations = target.deps.iter().filter(|dep| dep.starts_with(&format!("{}[", base_path)));
+        target_paths.extend(combinations.map(String::as_str));
+    }
+
+    walk(&reverse_edges, &target_paths)
+}
+
+pub fn run(kind: &str, target: &String, format: &String) {
+    let build_file = get_cwd().join("Emakefile");
+    let target_path = get_absolute_target_path(target, &build_file.to_string_lossy().to_string());
+    check_target_exists(&target_path);
+
+    let targets = match kind {
+        "deps" => get_deps(&target_path),
+        _ => get_rdeps(&target_path),
+    };
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&targets).unwrap());
+        return;
+    }
+
+    for target in targets {
+        println!("{}", target);
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use nix::{
+    errno::Errno,
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor},
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use walkdir::WalkDir;
+
+use crate::{
+    cache,
+    commands::build,
+    console::log,
+    get_cwd,
+    graph::{
+        self,
+        common::is_downloadable_file,
+        generator::to_emakefile_path,
+        runner::{get_compiled_in_files, get_real_out_files},
+    },
+    utils::{format_elapsed, get_absolute_file_path},
+};
+
+// Editors often write a file in several operations, wait for the changes to settle before building
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+struct WatchEvent {
+    path: PathBuf,
+    mask: AddWatchFlags,
+}
+
+/// Files the build depends on
+#[derive(Default)]
+struct WatchedFiles {
+    in_files: HashSet<PathBuf>,
+    out_files: HashSet<PathBuf>,
+    emakefiles: HashSet<PathBuf>,
+    directories: BTreeSet<PathBuf>,
+}
+
+struct Watcher {
+    inotify: Arc<Inotify>,
+    watch_descriptors: Arc<Mutex<HashMap<WatchDescriptor, PathBuf>>>,
+    watched_directories: HashSet<PathBuf>,
+}
+
+impl Watcher {
+    fn new() -> (Watcher, UnboundedReceiver<WatchEvent>) {
+        let inotify = match Inotify::init(InitFlags::IN_CLOEXEC) {
+            Ok(inotify) => Arc::new(inotify),
+            Err(error) => {
+                log::panic!("Can't watch files: {}", error);
+            }
+        };
+        let watch_descriptors: Arc<Mutex<HashMap<WatchDescriptor, PathBuf>>> =
+            Arc::new(Mutex::new(HashMap::new()));
+        let (sender, receiver) = unbounded_channel();
+
+        let inotify_clone = inotify.clone();
+        let watch_descriptors_clone = watch_descriptors.clone();
+        std::thread::spawn(move || loop {
+            let events = match inotify_clone.read_events() {
+                Ok(events) => events,
+                Err(Errno::EINTR) => continue,
+                Err(error) => {
+                    // Dropping the sender stops the watch command
+                    log::error!("Can't read the file events: {}", error);
+                    return;
+                }
+            };
+
+            for event in events {
+                let Some(directory) = watch_descriptors_clone.lock().unwrap().get(&event.wd).cloned() else {
+                    continue;
+                };
+                let path = match &event.name {
+                    Some(name) => directory.join(name),
+                    None => directory,
+                };
+
+                if sender.send(WatchEvent { path, mask: event.mask }).is_err() {
+                    return;
+                }
+            }
+        });
+
+        (
+            Watcher {
+                inotify,
+                watch_descriptors,
+                watched_directories: HashSet::new(),
+            },
+            receiver,
+        )
+    }
+
+    fn watch(&mut self, directory: &Path) {
+        if self.watched_directories.contains(directory) {
+            return;
+        }
+
+        let flags = AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_ATTRIB
+            | AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_DELETE
+            | AddWatchFlags::IN_MOVED_FROM
+            | AddWatchFlags::IN_MOVED_TO;
+
+        match self.inotify.add_watch(directory, flags) {
+            Ok(watch_descriptor) => {
+                self.watch_descriptors
+                    .lock()
+                    .unwrap()
+                    .insert(watch_descriptor, directory.to_path_buf());
+                self.watched_directories.insert(directory.to_path_buf());
+            }
+            Err(error) => {
+                log::debug!("Can't watch directory {}: {}", directory.to_string_lossy(), error);
+            }
+        }
+    }
+
+    fn watch_recursively(&mut self, directory: &Path) {
+        for entry in WalkDir::new(directory)
+            .into_iter()
+            .filter_entry(|entry| entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.'))
+            .filter_map(|entry| entry.ok())
+        {
+            if entry.file_type().is_dir() {
+                self.watch(entry.path());
+            }
+        }
+    }
+}
+
+fn is_hidden_from_build(path: &Path) -> bool {
+    path.starts_with(cache::get_emake_dir_path())
+}
+
+/// Resolve the in_files of every step reachable from the targets, globs are expanded again on each call
+async fn collect_in_files(target_paths: &Vec<String>, watched: &mut WatchedFiles) {
+    // An invalid graph is reported by the build, a change of an Emakefile is enough to build again
+    let Ok(build_graph) = graph::dag::build(target_paths) else {
+        return;
+    };
+
+    for target_path in build_graph.targets.keys() {
+        watched.emakefiles.insert(to_emakefile_path(target_path));
+    }
+
+    for node in &build_graph.steps {
+        for (file, _credentials) in get_compiled_in_files(&node.step, &node.emakefile_path).await {
+            if !is_downloadable_file(&file) {
+                watched.in_files.insert(get_absolute_file_path(&file));
+            }
+        }
+
+        for file in get_real_out_files(&node.id, &node.step, &node.emakefile_path).await {
+            watched.out_files.insert(get_absolute_file_path(&file));
+        }
+    }
+}
+
+async fn collect(target_paths: &Vec<String>, watcher: &mut Watcher) -> WatchedFiles {
+    let mut watched = WatchedFiles::default();
+    collect_in_files(target_paths, &mut watched).await;
+
+    // Watch the folders between the project and each file, so new files matching a glob are seen
+    let cwd = get_cwd();
+    for file in watched.in_files.iter().chain(watched.emakefiles.iter()) {
+        if file.is_dir() && !watched.out_files.contains(file) {
+            watcher.watch_recursively(file);
+        }
+
+        for ancestor in file.ancestors().skip(1) {
+            if !ancestor.starts_with(&cwd) {
+                break;
+            }
+            if ancestor.is_dir() {
+                watched.directories.insert(ancestor.to_path_buf());
+            }
+        }
+    }
+
+    for directory in &watched.directories {
+        watcher.watch(directory);
+    }
+
+    watched
+}
+
+/// What a file event means for the build
+enum Change {
+    Ignored,
+    /// A file of the build changed
+    Build(PathBuf),
+    /// A file was created or removed, it matters if it changes the result of a glob
+    CreatedOrRemoved(PathBuf),
+}
+
+fn classify(event: &WatchEvent, watched: &WatchedFiles, watcher: &mut Watcher) -> Change {
+    // Files written by the steps, or inside a folder written by a step
+    if is_hidden_from_build(&event.path) || watched.out_files.iter().any(|out_file| event.path.starts_with(out_file)) {
+        return Change::Ignored;
+    }
+
+    let is_created = event
+        .mask
+        .intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO);
+    if is_created && event.mask.contains(AddWatchFlags::IN_ISDIR) {
+        watcher.watch_recursively(&event.path);
+    }
+
+    if watched.in_files.contains(&event.path)
+        || watched.emakefiles.contains(&event.path)
+        || event.path.file_name().is_some_and(|name| name == "Emakefile")
+    {
+        return Change::Build(event.path.clone());
+    }
+
+    let is_created_or_removed = is_created
+        || event
+            .mask
+            .intersects(AddWatchFlags::IN_DELETE | AddWatchFlags::IN_MOVED_FROM);
+    match is_created_or_removed {
+        true => Change::CreatedOrRemoved(event.path.clone()),
+        false => Change::Ignored,
+    }
+}
+
+/// Wait for the changes to settle, a change of a file of the build wins over created or removed files
+async fn debounce(
+    mut change: Change,
+    events: &mut UnboundedReceiver<WatchEvent>,
+    watched: &WatchedFiles,
+    watcher: &mut Watcher,
+) -> Change {
+    while let Ok(Some(event)) = tokio::time::timeout(DEBOUNCE, events.recv()).await {
+        let event_change = classify(&event, watched, watcher);
+        if matches!(event_change, Change::Build(_)) && !matches!(change, Change::Build(_)) {
+            change = event_change;
+        }
+    }
+    change
+}
+
+/// Whether the created or removed files change the result of a glob, the globs are expanded again
+async fn have_in_files_changed(target_paths: &Vec<String>, watched: &WatchedFiles) -> bool {
+    let mut collected = WatchedFiles::default();
+    collect_in_files(target_paths, &mut collected).await;
+    collected.in_files != watched.in_files
+}
+
+fn report(start: Instant) {
+    let failed_steps = graph::runner::get_failed_steps();
+    let elapsed = format_elapsed(start.elapsed());
+
+    if failed_steps.is_empty() {
+        log::success!("Build successfully done in {}", elapsed);
+    } else {
+        build::print_failure_summary(&failed_steps);
+        log::error!("Build failed after {}", elapsed);
+    }
+
+    cache::write_cache(&!failed_steps.is_empty());
+}
+
+pub async fn run(targets: &[String]) {
+    build::handle_ctrl_c();
+
+    let target_paths = build::resolve_targets(targets);
+    let (mut watcher, mut events) = Watcher::new();
+
+    loop {
+        let watched = collect(&target_paths, &mut watcher).await;
+        log::debug!(
+            "Watching {} files in {} folders",
+            watched.in_files.len(),
+            watched.directories.len()
+        );
+
+        graph::runner::reset();
+        build::reset_progress();
+        let start = Instant::now();
+        let mut build_task = None;
+        match graph::dag::build(&target_paths) {
+            Ok(build_graph) => build_task = Some(tokio::spawn(graph::runner::run_graph(build_graph))),
+            Err(errors) => {
+                // Keep watching, the Emakefile will probably be fixed soon
+                for error in &errors {
+                    log::error!("{}", error);
+                }
+                log::info!("Waiting for changes...");
+            }
+        }
+
+        // The build keeps running while waiting for a change
+        let changed_path = loop {
+            let change = tokio::select! {
+                _ = async { build_task.as_mut().unwrap().await }, if build_task.is_some() => {
+                    build_task = None;
+                    report(start);
+                    log::info!("Waiting for changes...");
+                    continue;
+                }
+                maybe_event = events.recv() => {
+                    let Some(event) = maybe_event else {
+                        log::panic!("The file watcher stopped unexpectedly");
+                    };
+                    classify(&event, &watched, &mut watcher)
+                }
+            };
+            if matches!(change, Change::Ignored) {
+                continue;
+            }
+
+            // The globs are expanded once for all the files created or removed meanwhile
+            match debounce(change, &mut events, &watched, &mut watcher).await {
+                Change::Build(path) => break path,
+                Change::CreatedOrRemoved(path) if have_in_files_changed(&target_paths, &watched).await => break path,
+                _ => {}
+            }
+        };
+
+        if let Some(build_task) = build_task {
+            log::warning!("Cancelling the current build, a newer change arrived");
+            build_task.abort();
+            graph::runner::cancel();
+            let _ = build_task.await;
+            cache::write_cache(&true);
+        }
+
+        log::info!("Change detected in {}, building again", changed_path.to_string_lossy());
+    }
+}
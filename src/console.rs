@@ -1,2 +1,3 @@
+pub mod events;
 pub mod log;
 pub mod progress_bar;
\ No newline at end of file
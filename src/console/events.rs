@@ -0,0 +1,134 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    sync::{Mutex, OnceLock},
+};
+
+use serde::Serialize;
+
+use crate::{console::log, report, step_logs};
+
+// Build events written as JSON lines by `emake build --events <file|->`.
+// Increase the version on any breaking change of the events below, and update the events reference.
+pub const EVENTS_VERSION: u32 = 1;
+
+static EVENTS_WRITER: OnceLock<Mutex<Box<dyn Write + Send>>> = OnceLock::new();
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    BuildStarted {
+        targets: &'a Vec<String>,
+    },
+    BuildFinished {
+        success: bool,
+        duration_ms: Option<u128>,
+    },
+    TargetStarted {
+        target: &'a str,
+    },
+    TargetFinished {
+        target: &'a str,
+        success: bool,
+    },
+    StepStarted {
+        step_id: &'a str,
+        target: &'a str,
+        description: &'a str,
+        reason: String,
+    },
+    StepSkipped {
+        step_id: &'a str,
+        target: &'a str,
+        description: &'a str,
+        reason: String,
+    },
+    StepWouldRun {
+        step_id: &'a str,
+        target: &'a str,
+        description: &'a str,
+        reason: String,
+    },
+    StepFinished {
+        step_id: &'a str,
+        duration_ms: u128,
+    },
+    StepFailed {
+        step_id: &'a str,
+        duration_ms: u128,
+        exit_code: Option<i32>,
+        error: String,
+    },
+    StepRetried {
+        step_id: &'a str,
+        attempt: u32,
+        attempts: u32,
+        delay_ms: u128,
+        exit_code: Option<i32>,
+        error: String,
+    },
+    Output {
+        step_id: &'a str,
+        stream: &'a str,
+        line: &'a str,
+    },
+    DownloadProgress {
+        step_id: &'a str,
+        url: &'a str,
+        percent: usize,
+    },
+    CacheHit {
+        step_id: &'a str,
+        key: &'a str,
+    },
+    CacheMiss {
+        step_id: &'a str,
+        key: &'a str,
+    },
+}
+
+#[derive(Serialize)]
+struct EventLine<'a> {
+    version: u32,
+    timestamp: String,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+/// Write the events in a file, or on stdout with `-`. Logs are moved to stderr when events use stdout.
+pub fn set_output(path: &str) {
+    let writer: Box<dyn Write + Send> = if path == "-" {
+        log::set_log_to_stderr(true);
+        Box::new(io::stdout())
+    } else {
+        match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(error) => {
+                log::panic!("Can't create the events file {}: {}", path, error);
+            }
+        }
+    };
+
+    EVENTS_WRITER.set(Mutex::new(writer)).ok();
+}
+
+pub fn emit(event: Event) {
+    report::record(&event);
+    step_logs::record(&event);
+
+    let Some(writer) = EVENTS_WRITER.get() else {
+        return;
+    };
+
+    let line = EventLine {
+        version: EVENTS_VERSION,
+        timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+        event,
+    };
+
+    let mut writer = writer.lock().unwrap();
+    // Events are read while the build runs, don't keep them in the buffer
+    let _ = serde_json::to_writer(&mut *writer, &line);
+    let _ = writer.write_all(b"\n");
+    let _ = writer.flush();
+}
@@ -1,5 +1,6 @@
 // Global variable
 static LOG_LEVEL: AtomicUsize = AtomicUsize::new(0); // Default = Info
+static LOG_TO_STDERR: AtomicBool = AtomicBool::new(false);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LogLevel {
@@ -46,10 +47,20 @@ pub fn get_log_level() -> LogLevel {
     LogLevel::from_usize(LOG_LEVEL.load(Ordering::Relaxed))
 }
 
+/// Keep stdout for machine-readable output, like the build events
+pub fn set_log_to_stderr(log_to_stderr: bool) {
+    LOG_TO_STDERR.store(log_to_stderr, Ordering::Relaxed);
+}
+
+pub fn is_log_to_stderr() -> bool {
+    LOG_TO_STDERR.load(Ordering::Relaxed)
+}
+
 pub enum StepStatus {
     Finished,
     Running,
     Skipped,
+    WouldRun,
 }
 
 impl fmt::Display for StepStatus {
@@ -58,6 +69,7 @@ impl fmt::Display for StepStatus {
             StepStatus::Skipped => write!(f, "\x1b[1;90mSkipped\x1b[0m"), // gray
             StepStatus::Finished => write!(f, "\x1b[1;32mFinished\x1b[0m"), // green
             StepStatus::Running => write!(f, "\x1b[1;32mRunning\x1b[0m"), // green
+            StepStatus::WouldRun => write!(f, "\x1b[1;33mWouldRun\x1b[0m"), // yellow
         }
     }
 }
@@ -144,6 +156,19 @@ macro_rules! warning {
     };
 }
 
+#[allow(unused)]
+macro_rules! error {
+    // `()` indicates that the macro takes no argument.
+    ($($arg:tt)*) => {
+        if log::LogLevel::as_usize(log::get_log_level()) > 0 {
+            // The macro will expand into the contents of this block.
+            log::timestamp!("[\x1b[31merror\x1b[0m] \x1b[1;91m{}\x1b[0m", format!($($arg)*));
+        } else {
+            log::text!("\x1b[1;91m{}\x1b[0m", format!($($arg)*));
+        }
+    };
+}
+
 #[allow(unused)]
 macro_rules! panic {
     // `()` indicates that the macro takes no argument.
@@ -186,18 +211,25 @@ macro_rules! timestamp {
     ($($arg:tt)*) => {{
         let now = std::time::SystemTime::now();
         let dt: chrono::prelude::DateTime<chrono::prelude::Utc> = now.into();
-        println!("{}", format!("[{}] {}", dt.format("%+"), format!($($arg)*)));
+        let line = format!("[{}] {}", dt.format("%+"), format!($($arg)*));
+        if log::is_log_to_stderr() {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
     }}
 }
 
 use std::{
     fmt,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
 #[allow(unused)]
 pub(crate) use debug;
 #[allow(unused)]
+pub(crate) use error;
+#[allow(unused)]
 pub(crate) use info;
 #[allow(unused)]
 pub(crate) use panic;
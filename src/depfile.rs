@@ -0,0 +1,119 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::utils::normalize_path;
+
+// Makefile-style dependency files, like the `.d` files written by `gcc -MD`:
+//   main.o: src/main.c src/math.h \
+//     src/other\ header.h
+// Every prerequisite of every rule is returned, the targets are ignored.
+
+/// Whether the `:` at this index follows the drive letter of a Windows path, like `C:\src\main.c`
+fn is_drive_separator(bytes: &[u8], index: usize) -> bool {
+    index > 0
+        && bytes[index - 1].is_ascii_alphabetic()
+        && (index == 1 || bytes[index - 2].is_ascii_whitespace())
+        && matches!(bytes.get(index + 1), Some(b'\\') | Some(b'/'))
+}
+
+/// Split a rule on its first unescaped `:` separating the targets from the prerequisites,
+/// `main.o:main.c` is a rule too, `C:\` is not a separator
+fn split_rule(rule: &str) -> Option<(&str, &str)> {
+    let bytes = rule.as_bytes();
+    for (index, byte) in bytes.iter().enumerate() {
+        if *byte != b':' || (index > 0 && bytes[index - 1] == b'\\') || is_drive_separator(bytes, index) {
+            continue;
+        }
+        // The second `:` of a double-colon rule `main.o:: main.c`
+        let prerequisites = &rule[index + 1..];
+        return Some((&rule[..index], prerequisites.strip_prefix(':').unwrap_or(prerequisites)));
+    }
+    None
+}
+
+/// Split the prerequisites on whitespaces, `\ `, `\#` and `$$` are unescaped
+fn split_prerequisites(prerequisites: &str) -> Vec<String> {
+    let mut paths = Vec::new();
+    let mut current = String::new();
+    let mut chars = prerequisites.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(' ') | Some('#') | Some(':')) => {
+                current.push(chars.next().unwrap());
+            }
+            '$' if chars.peek() == Some(&'$') => {
+                chars.next();
+                current.push('$');
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    paths.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        paths.push(current);
+    }
+    paths
+}
+
+pub fn parse(content: &str) -> Vec<String> {
+    let joined = content.replace("\\\r\n", " ").replace("\\\n", " ");
+    let mut prerequisites: Vec<String> = Vec::new();
+
+    for rule in joined.lines() {
+        let rule = rule.trim();
+        if rule.is_empty() || rule.starts_with('#') {
+            continue;
+        }
+        let Some((_targets, rule_prerequisites)) = split_rule(rule) else {
+            continue;
+        };
+        for prerequisite in split_prerequisites(rule_prerequisites) {
+            if !prerequisites.contains(&prerequisite) {
+                prerequisites.push(prerequisite);
+            }
+        }
+    }
+
+    prerequisites
+}
+
+/// Prerequisites of a depfile as absolute paths, relative paths are relative to the folder of the command
+pub fn read(depfile_path: &Path, command_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let content = fs::read_to_string(depfile_path)
+        .map_err(|error| format!("Can't read the depfile {}: {}", depfile_path.to_string_lossy(), error))?;
+
+    Ok(parse(&content)
+        .into_iter()
+        .map(|prerequisite| normalize_path(command_dir.join(prerequisite)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn rule_without_space_after_colon() {
+        assert_eq!(parse("out.o:dep.h other.h\n"), Vec::from(["dep.h", "other.h"]));
+    }
+
+    #[test]
+    fn windows_drive_letters_are_not_separators() {
+        assert_eq!(
+            parse("C:\\build\\main.o: C:\\src\\main.c D:/include/math.h\n"),
+            Vec::from(["C:\\src\\main.c", "D:/include/math.h"])
+        );
+    }
+
+    #[test]
+    fn escaped_colons_and_double_colon_rules() {
+        assert_eq!(parse("out\\:1.o:: src/a\\:b.h \\\n  src/c.h\n"), Vec::from(["src/a:b.h", "src/c.h"]));
+    }
+}
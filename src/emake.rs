@@ -1,21 +1,45 @@
 use config_macros::DocType;
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_yml::Value;
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
 
 use crate::actions::{archive, copy, extract, git_clone, mv, remove, shell, yaml};
 
 pub mod compiler;
+pub mod condition;
+pub mod env;
+pub mod for_each;
 pub mod loader;
+pub mod params;
+pub mod retry;
 
 pub type SecretEntry = HashMap<String, Value>;
 pub type VariableEntry = Value;
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ChangeDetection {
+    #[default]
+    #[serde(rename = "mtime")]
+    Mtime,
+    #[serde(rename = "hash")]
+    Hash,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Target {
+    pub description: Option<String>,
     pub deps: Option<Vec<String>>,
+    /// Expression deciding if the steps of the target run, see `condition`
+    pub when: Option<String>,
     pub parallel_steps: Option<bool>,
     pub parallel_deps: Option<bool>,
+    /// Values of the parameters, the target is built for each combination, see `params`
+    pub matrix: Option<BTreeMap<String, Vec<String>>>,
+    #[serde(flatten)]
+    pub env: env::EnvConfig,
     pub steps: Option<Vec<Step>>,
 }
 
@@ -88,8 +112,22 @@ pub struct Credentials {
 // #[serde(deny_unknown_fields)]
 pub struct Step {
     pub description: String,
+    pub when: Option<String>,
+    /// List of values, the step is repeated for each value
+    pub for_each: Option<String>,
+    pub change_detection: Option<ChangeDetection>,
+    pub weight: Option<u32>,
+    pub pool: Option<String>,
+    /// Maximum duration of an attempt of the step, see `retry`
+    pub timeout: Option<Duration>,
+    pub retry: Option<retry::RetryPolicy>,
+    #[serde(flatten)]
+    pub env: env::EnvConfig,
     #[serde(flatten)]
     pub action: PluginAction, // The actual action like cmd/copy
+    /// `item` and `item_index` of a step repeated by for_each
+    #[serde(skip)]
+    pub replacements: HashMap<String, String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -122,11 +160,46 @@ pub enum PluginAction {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteCacheConfig {
+    pub url: Option<String>,
+    pub path: Option<String>,
+    pub read_only: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum DefaultTargets {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl DefaultTargets {
+    pub fn to_vec(&self) -> Vec<String> {
+        match self {
+            DefaultTargets::One(target) => Vec::from([target.clone()]),
+            DefaultTargets::Many(targets) => targets.clone(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Emakefile {
     pub path: Option<String>,
+    /// Targets built when `emake build` is called without target
+    pub default: Option<DefaultTargets>,
+    pub change_detection: Option<ChangeDetection>,
+    pub remote_cache: Option<RemoteCacheConfig>,
+    /// Pool name => number of steps of this pool allowed to run at the same time
+    pub pools: Option<HashMap<String, usize>>,
+    /// Number of runs of each step whose log is kept
+    pub kept_logs: Option<usize>,
     pub secrets: Option<HashMap<String, SecretEntry>>,
     pub variables: Option<HashMap<String, VariableEntry>>,
+    /// Environment of the commands of every target of this Emakefile
+    #[serde(flatten)]
+    pub env: env::EnvConfig,
     pub targets: HashMap<String, Target>,
 }
 
@@ -151,6 +224,14 @@ impl<'de> Deserialize<'de> for Step {
         let raw: serde_yml::Mapping = Deserialize::deserialize(deserializer)?;
 
         let mut description: Option<String> = None;
+        let mut when: Option<String> = None;
+        let mut for_each: Option<String> = None;
+        let mut change_detection: Option<ChangeDetection> = None;
+        let mut weight: Option<u32> = None;
+        let mut pool: Option<String> = None;
+        let mut timeout: Option<Duration> = None;
+        let mut retry: Option<retry::RetryPolicy> = None;
+        let mut env = env::EnvConfig::default();
         let mut action: Option<PluginAction> = None;
 
         for (k, v) in &raw {
@@ -162,6 +243,40 @@ impl<'de> Deserialize<'de> for Step {
                     description =
                         Some(String::deserialize(v.clone()).map_err(serde::de::Error::custom)?);
                 }
+                "when" => {
+                    when = Some(String::deserialize(v.clone()).map_err(serde::de::Error::custom)?);
+                }
+                "for_each" => {
+                    for_each = Some(String::deserialize(v.clone()).map_err(serde::de::Error::custom)?);
+                }
+                "change_detection" => {
+                    change_detection = Some(
+                        ChangeDetection::deserialize(v.clone()).map_err(serde::de::Error::custom)?,
+                    );
+                }
+                "weight" => {
+                    weight = Some(u32::deserialize(v.clone()).map_err(serde::de::Error::custom)?);
+                }
+                "pool" => {
+                    pool = Some(String::deserialize(v.clone()).map_err(serde::de::Error::custom)?);
+                }
+                "timeout" => {
+                    timeout = Some(retry::duration_from_value(v).map_err(serde::de::Error::custom)?);
+                }
+                "retry" => {
+                    let retry_policy = retry::RetryPolicy::deserialize(v.clone()).map_err(serde::de::Error::custom)?;
+                    retry_policy.check().map_err(serde::de::Error::custom)?;
+                    retry = Some(retry_policy);
+                }
+                "env" => {
+                    env.env = Some(serde_yml::from_value(v.clone()).map_err(serde::de::Error::custom)?);
+                }
+                "env_clear" => {
+                    env.env_clear = Some(bool::deserialize(v.clone()).map_err(serde::de::Error::custom)?);
+                }
+                "env_passthrough" => {
+                    env.env_passthrough = Some(serde_yml::from_value(v.clone()).map_err(serde::de::Error::custom)?);
+                }
                 key if key == shell::ID => {
                     let deserialized_action: shell::ShellAction =
                         serde_yml::from_value(v.clone()).map_err(serde::de::Error::custom)?;
@@ -246,7 +361,7 @@ impl<'de> Deserialize<'de> for Step {
                 // Add other actions: copy, extract, move, remove...
                 _ => {
                     return Err(serde::de::Error::custom(format!(
-                        "Unknown key `{}` (expected field description or an action)",
+                        "Unknown key `{}` (expected field description, when, for_each, change_detection, weight, pool, timeout, retry, env, env_clear, env_passthrough or an action)",
                         key
                     )));
                 }
@@ -270,9 +385,27 @@ impl<'de> Deserialize<'de> for Step {
             ))
         })?;
 
+        // The other actions run in emake and can't be stopped when their step times out
+        if timeout.is_some() && !matches!(action, PluginAction::Shell { .. } | PluginAction::GitClone { .. }) {
+            return Err(serde::de::Error::custom(format!(
+                "`timeout` is only supported by the {} and {} actions",
+                shell::ID,
+                git_clone::ID
+            )));
+        }
+
         Ok(Step {
             description,
+            when,
+            for_each,
+            change_detection,
+            weight,
+            pool,
+            timeout,
+            retry,
+            env,
             action,
+            replacements: HashMap::new(),
         })
     }
 }
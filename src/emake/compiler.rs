@@ -1,4 +1,4 @@
-use std::{collections::HashMap, path::{Path, PathBuf}};
+use std::{cell::Cell, collections::HashMap, path::{Path, PathBuf}};
 
 use glob::glob;
 use regex::Regex;
@@ -10,6 +10,11 @@ use crate::{
     }, get_cwd
 };
 
+thread_local! {
+    // Secrets resolved by the thread, a text contains a secret when the count changes while compiling it
+    static RESOLVED_SECRETS: Cell<usize> = const { Cell::new(0) };
+}
+
 #[derive(PartialEq)]
 enum TOKEN_STATE {
     UNKNOWN,
@@ -209,6 +214,7 @@ fn resolve_secret(
                 let maybe_credential_plugin = CREDENTIALS_STORE.get(&credential_type);
                 match maybe_credential_plugin {
                     Some(credential_plugin) => {
+                        RESOLVED_SECRETS.set(RESOLVED_SECRETS.get() + 1);
                         return Some(credential_plugin.extract(&raw_credential));
                     }
                     None => {
@@ -370,7 +376,7 @@ fn stringify_variable_value(value: &impl serde::Serialize) -> String {
     }
 }
 
-fn get_user_variable(
+pub fn get_user_variable(
     user_variable: &String,
     emakefile_current_path: &str,
 ) -> Result<String, String> {
@@ -801,6 +807,17 @@ pub fn compile(
     maybe_replacements: Option<&HashMap<String, String>>,
     cwd: Option<&str>,
 ) -> String {
+    compile_with_secrets(content, emakefile_current_path, maybe_replacements, cwd).0
+}
+
+/// Compile a text and tell whether a secret was substituted in it, directly or through a variable
+pub fn compile_with_secrets(
+    content: &str,
+    emakefile_current_path: &str,
+    maybe_replacements: Option<&HashMap<String, String>>,
+    cwd: Option<&str>,
+) -> (String, bool) {
+    let resolved_secrets = RESOLVED_SECRETS.get();
     let re = Regex::new(r"\{\{(.*?)\}\}").unwrap();
     let result = re.replace_all(content, |caps: &regex::Captures| {
         let element = String::from(caps[1].trim());
@@ -809,7 +826,11 @@ pub fn compile(
         template_executor(&tokens, emakefile_current_path, maybe_replacements, cwd)
     });
 
-    log::debug!("Template compilation result from {} to {}", content, result);
+    // The value of a secret must not end up in the logs
+    let has_secret = RESOLVED_SECRETS.get() != resolved_secrets;
+    if !has_secret {
+        log::debug!("Template compilation result from {} to {}", content, result);
+    }
 
-    result.to_string()
+    (result.to_string(), has_secret)
 }
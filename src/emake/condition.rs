@@ -0,0 +1,288 @@
+use std::fmt;
+
+use crate::emake::compiler;
+
+// Language of the `when` field of targets and steps:
+//   os == "linux" && (arch == "x86_64" || env:CI != "") && !variables:skip_tests
+// Values are strings: `os`, `arch`, `env:NAME` for an environment variable (empty when not set)
+// and `variables:name` or any variable path. A value alone is true unless empty, "false" or "0".
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    String(String),
+    Name(String),
+    Equal,
+    NotEqual,
+    And,
+    Or,
+    Not,
+    OpenParenthesis,
+    CloseParenthesis,
+}
+
+#[derive(Debug, Clone)]
+enum Expression {
+    String(String),
+    Name(String),
+    Equal(Box<Expression>, Box<Expression>),
+    NotEqual(Box<Expression>, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+}
+
+/// A parsed `when` expression
+#[derive(Debug, Clone)]
+pub struct Condition {
+    source: String,
+    expression: Expression,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':' | '/' | '[' | ']')
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '"' | '\'' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => value.extend(chars.next()),
+                        Some(next) if next == c => break,
+                        Some(next) => value.push(next),
+                        None => return Err(format!("Missing closing quote {} after \"{}\"", c, value)),
+                    }
+                }
+                tokens.push(Token::String(value));
+            }
+            '=' if chars.next_if_eq(&'=').is_some() => tokens.push(Token::Equal),
+            '!' if chars.next_if_eq(&'=').is_some() => tokens.push(Token::NotEqual),
+            '!' => tokens.push(Token::Not),
+            '&' if chars.next_if_eq(&'&').is_some() => tokens.push(Token::And),
+            '|' if chars.next_if_eq(&'|').is_some() => tokens.push(Token::Or),
+            '(' => tokens.push(Token::OpenParenthesis),
+            ')' => tokens.push(Token::CloseParenthesis),
+            c if is_name_char(c) => {
+                let mut name = String::from(c);
+                while let Some(next) = chars.next_if(|next| is_name_char(*next)) {
+                    name.push(next);
+                }
+                tokens.push(Token::Name(name));
+            }
+            c => return Err(format!("Unexpected character `{}`", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next_if(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.position) == Some(token) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, String> {
+        let mut expression = self.parse_and()?;
+        while self.next_if(&Token::Or) {
+            expression = Expression::Or(Box::new(expression), Box::new(self.parse_and()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, String> {
+        let mut expression = self.parse_not()?;
+        while self.next_if(&Token::And) {
+            expression = Expression::And(Box::new(expression), Box::new(self.parse_not()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_not(&mut self) -> Result<Expression, String> {
+        if self.next_if(&Token::Not) {
+            return Ok(Expression::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expression, String> {
+        let left = self.parse_value()?;
+        if self.next_if(&Token::Equal) {
+            return Ok(Expression::Equal(Box::new(left), Box::new(self.parse_value()?)));
+        }
+        if self.next_if(&Token::NotEqual) {
+            return Ok(Expression::NotEqual(Box::new(left), Box::new(self.parse_value()?)));
+        }
+        Ok(left)
+    }
+
+    fn parse_value(&mut self) -> Result<Expression, String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::String(value)) => Ok(Expression::String(value)),
+            Some(Token::Name(name)) => Ok(Expression::Name(name)),
+            Some(Token::OpenParenthesis) => {
+                let expression = self.parse_or()?;
+                if !self.next_if(&Token::CloseParenthesis) {
+                    return Err(String::from("Missing closing parenthesis"));
+                }
+                Ok(expression)
+            }
+            Some(token) => Err(format!("Unexpected {:?}, expected a value", token)),
+            None => Err(String::from("Unexpected end of the expression, expected a value")),
+        }
+    }
+}
+
+fn is_true(value: &str) -> bool {
+    !matches!(value, "" | "false" | "0")
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Condition, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let expression = parser.parse_or()?;
+        if parser.position < parser.tokens.len() {
+            return Err(format!("Unexpected {:?} after the expression", parser.tokens[parser.position]));
+        }
+
+        let condition = Condition {
+            source: String::from(source),
+            expression,
+        };
+        condition.check_names(&condition.expression)?;
+        Ok(condition)
+    }
+
+    fn check_names(&self, expression: &Expression) -> Result<(), String> {
+        match expression {
+            Expression::String(_) => Ok(()),
+            Expression::Name(name) => match name.as_str() {
+                "os" | "arch" | "true" | "false" => Ok(()),
+                name if name.starts_with("env:") || name.contains("variables:") => Ok(()),
+                name => Err(format!(
+                    "Unknown name `{}`, expected os, arch, env:NAME, variables:name or a quoted string",
+                    name
+                )),
+            },
+            Expression::Equal(left, right)
+            | Expression::NotEqual(left, right)
+            | Expression::And(left, right)
+            | Expression::Or(left, right) => {
+                self.check_names(left)?;
+                self.check_names(right)
+            }
+            Expression::Not(expression) => self.check_names(expression),
+        }
+    }
+
+    fn get_value(&self, expression: &Expression, emakefile_current_path: &str) -> Result<String, String> {
+        match expression {
+            Expression::String(value) => Ok(value.clone()),
+            Expression::Name(name) => match name.as_str() {
+                "os" => Ok(String::from(std::env::consts::OS)),
+                "arch" => Ok(String::from(std::env::consts::ARCH)),
+                "true" | "false" => Ok(name.clone()),
+                name => match name.strip_prefix("env:") {
+                    Some(env_name) => Ok(std::env::var(env_name).unwrap_or_default()),
+                    None => compiler::get_user_variable(&String::from(name), emakefile_current_path),
+                },
+            },
+            expression => Ok(self.evaluate_expression(expression, emakefile_current_path)?.to_string()),
+        }
+    }
+
+    fn evaluate_expression(&self, expression: &Expression, emakefile_current_path: &str) -> Result<bool, String> {
+        match expression {
+            Expression::Equal(left, right) => Ok(self.get_value(left, emakefile_current_path)?
+                == self.get_value(right, emakefile_current_path)?),
+            Expression::NotEqual(left, right) => Ok(self.get_value(left, emakefile_current_path)?
+                != self.get_value(right, emakefile_current_path)?),
+            Expression::And(left, right) => Ok(self.evaluate_expression(left, emakefile_current_path)?
+                && self.evaluate_expression(right, emakefile_current_path)?),
+            Expression::Or(left, right) => Ok(self.evaluate_expression(left, emakefile_current_path)?
+                || self.evaluate_expression(right, emakefile_current_path)?),
+            Expression::Not(expression) => Ok(!self.evaluate_expression(expression, emakefile_current_path)?),
+            value => Ok(is_true(&self.get_value(value, emakefile_current_path)?)),
+        }
+    }
+
+    pub fn evaluate(&self, emakefile_current_path: &str) -> Result<bool, String> {
+        self.evaluate_expression(&self.expression, emakefile_current_path)
+            .map_err(|error| format!("Can't evaluate the condition `{}`: {}", self.source, error))
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(source: &str) -> bool {
+        Condition::parse(source).unwrap().evaluate("").unwrap()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert!(evaluate("true || false && false"));
+        assert!(!evaluate("(true || false) && false"));
+        assert!(evaluate("\"a\" == \"b\" || \"c\" == \"c\" && \"d\" != \"e\""));
+    }
+
+    #[test]
+    fn not_applies_to_the_next_value() {
+        assert!(evaluate("!false && true"));
+        assert!(!evaluate("!(false || true)"));
+        assert!(evaluate("!!true"));
+        assert!(evaluate("!\"\" && !'0'"));
+    }
+
+    #[test]
+    fn strings_are_quoted_and_escaped() {
+        assert!(evaluate(r#"'it\'s' == "it's""#));
+        assert!(evaluate(r#""a\"b" == 'a"b'"#));
+        assert!(evaluate(r#""&& ||" != """#));
+        assert!(evaluate("env:EMAKE_CONDITION_TEST_UNSET == ''"));
+        assert!(Condition::parse("\"linux").is_err());
+    }
+
+    #[test]
+    fn unknown_names_are_errors() {
+        let error = Condition::parse("platform == \"linux\"").unwrap_err();
+        assert!(error.contains("Unknown name `platform`"), "{}", error);
+        assert!(Condition::parse("os == linux").is_err());
+        assert!(Condition::parse("os == \"linux\" && //variables:ci").is_ok());
+    }
+
+    #[test]
+    fn malformed_expressions_are_errors() {
+        assert!(Condition::parse("(os == \"linux\"").is_err());
+        assert!(Condition::parse("os == \"linux\")").is_err());
+        assert!(Condition::parse("os ==").is_err());
+        assert!(Condition::parse("os = \"linux\"").is_err());
+        assert!(Condition::parse("").is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    process::Command,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::emake::compiler;
+
+const SECRET_MASK: &str = "****";
+
+/// Environment variables of the commands, defined on an Emakefile, a target or a step
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct EnvConfig {
+    /// Variables set for the commands, values are compiled with the template language
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<BTreeMap<String, String>>,
+    /// Start the commands from an empty environment instead of the environment of emake
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env_clear: Option<bool>,
+    /// Variables of the environment of emake kept when env_clear is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env_passthrough: Option<Vec<String>>,
+}
+
+impl EnvConfig {
+    /// Merge a more specific config, its variables and env_clear override ours, passthrough lists are added
+    pub fn merge(&self, other: &EnvConfig) -> EnvConfig {
+        let mut env = self.env.clone();
+        if let Some(other_env) = &other.env {
+            env.get_or_insert_with(BTreeMap::new)
+                .extend(other_env.iter().map(|(name, value)| (name.clone(), value.clone())));
+        }
+
+        let mut env_passthrough = self.env_passthrough.clone();
+        if let Some(other_passthrough) = &other.env_passthrough {
+            let passthrough = env_passthrough.get_or_insert_with(Vec::new);
+            for name in other_passthrough {
+                if !passthrough.contains(name) {
+                    passthrough.push(name.clone());
+                }
+            }
+        }
+
+        EnvConfig {
+            env,
+            env_clear: other.env_clear.or(self.env_clear),
+            env_passthrough,
+        }
+    }
+
+    /// Compile the values of the variables, done when the step runs so secrets are only read when needed
+    pub fn resolve(&self, emakefile_current_path: &str, replacements: &HashMap<String, String>) -> StepEnv {
+        let mut vars = BTreeMap::new();
+        let mut secrets = BTreeSet::new();
+        for (name, value) in self.env.iter().flatten() {
+            let (value, has_secret) = compiler::compile_with_secrets(value, emakefile_current_path, Some(replacements), None);
+            if has_secret {
+                secrets.insert(name.clone());
+            }
+            vars.insert(name.clone(), value);
+        }
+
+        StepEnv {
+            vars,
+            secrets,
+            clear: self.env_clear.unwrap_or(false),
+            passthrough: self.env_passthrough.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Resolved environment of the commands of a step
+#[derive(Debug, Clone, Default)]
+pub struct StepEnv {
+    pub vars: BTreeMap<String, String>,
+    /// Variables whose value comes from a secret, never written in logs nor footprints
+    pub secrets: BTreeSet<String>,
+    pub clear: bool,
+    pub passthrough: Vec<String>,
+}
+
+impl StepEnv {
+    fn is_empty(&self) -> bool {
+        self.vars.is_empty() && !self.clear && self.passthrough.is_empty()
+    }
+
+    pub fn apply(&self, command: &mut Command) {
+        if self.clear {
+            command.env_clear();
+            for name in &self.passthrough {
+                if let Some(value) = std::env::var_os(name) {
+                    command.env(name, value);
+                }
+            }
+        }
+        command.envs(&self.vars);
+    }
+
+    fn get_masked_vars(&self) -> BTreeMap<&String, &str> {
+        self.vars
+            .iter()
+            .map(|(name, value)| match self.secrets.contains(name) {
+                true => (name, SECRET_MASK),
+                false => (name, value.as_str()),
+            })
+            .collect()
+    }
+
+    /// Values of the passthrough variables when the environment is cleared, None for an unset variable
+    fn get_masked_passthrough(&self) -> BTreeMap<&String, Option<String>> {
+        self.passthrough
+            .iter()
+            .filter(|_| self.clear)
+            .map(|name| match self.secrets.contains(name) {
+                true => (name, Some(String::from(SECRET_MASK))),
+                false => (name, std::env::var_os(name).map(|value| value.to_string_lossy().into_owned())),
+            })
+            .collect()
+    }
+
+    /// Part of the step footprint, None when no environment is defined so existing footprints don't change
+    pub fn get_footprint(&self) -> Option<Value> {
+        if self.is_empty() {
+            return None;
+        }
+
+        Some(json!({
+            "env": self.get_masked_vars(),
+            "env_clear": self.clear,
+            "env_passthrough": self.get_masked_passthrough(),
+        }))
+    }
+
+    /// Hide the values of the secrets in a text written by a command
+    pub fn mask(&self, text: &str) -> String {
+        let mut masked = String::from(text);
+        for name in &self.secrets {
+            if let Some(value) = self.vars.get(name).filter(|value| !value.is_empty()) {
+                masked = masked.replace(value.as_str(), SECRET_MASK);
+            }
+        }
+        masked
+    }
+
+    /// Variables as written in the logs, secrets are masked
+    pub fn describe(&self) -> String {
+        self.get_masked_vars()
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::emake::{compiler, Step};
+
+// `for_each: "{{ //variables:plugins | keys_of }}"` repeats a step for each value of a list,
+// the step uses the value with `{{ item }}` and its position with `{{ item_index }}`.
+// The list is a JSON array, like the output of keys_of and values_of, or values separated by whitespaces.
+// A repeated step has its own id `<target>/<step index>/<item>`, so its own footprint and cache entries,
+// adding a value to the list doesn't invalidate the steps of the other values.
+
+/// Position of the item, only part of the footprint of the actions using it
+pub const ITEM_INDEX: &str = "item_index";
+
+fn parse_items(list: &str) -> Vec<String> {
+    match serde_json::from_str::<Vec<serde_json::Value>>(list) {
+        Ok(values) => values
+            .into_iter()
+            .map(|value| match value {
+                serde_json::Value::String(value) => value,
+                value => value.to_string(),
+            })
+            .collect(),
+        Err(_) => list.split_whitespace().map(String::from).collect(),
+    }
+}
+
+/// Part of the step id for an item, the id is also used as a folder name
+fn to_id_part(item: &str) -> String {
+    item.chars()
+        .map(|c| match c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+/// Steps to run for a step of a target with their id, the step itself when it has no for_each
+pub fn expand(
+    step_id: &str,
+    step: &Step,
+    emakefile_current_path: &str,
+    replacements: &HashMap<String, String>,
+) -> Result<Vec<(String, Step)>, String> {
+    let Some(for_each) = &step.for_each else {
+        return Ok(Vec::from([(String::from(step_id), step.clone())]));
+    };
+
+    let list = compiler::compile(for_each, emakefile_current_path, Some(replacements), None);
+    let mut ids = HashSet::new();
+    let mut steps = Vec::new();
+    for (item_index, item) in parse_items(&list).into_iter().enumerate() {
+        let item_step_id = format!("{}/{}", step_id, to_id_part(&item));
+        if !ids.insert(item_step_id.clone()) {
+            return Err(format!("The item `{}` is repeated, the step id {} must be unique", item, item_step_id));
+        }
+
+        let mut item_step = step.clone();
+        item_step.replacements.insert(String::from("item"), item);
+        item_step.replacements.insert(String::from(ITEM_INDEX), item_index.to_string());
+        steps.push((item_step_id, item_step));
+    }
+
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_step(for_each: &str) -> Result<Vec<(String, Step)>, String> {
+        let step: Step = serde_yml::from_str(&format!("for_each: '{}'\nshell:\n  cmd: echo {{{{ item }}}}", for_each)).unwrap();
+        expand("//targets:build/0", &step, "", &HashMap::new())
+    }
+
+    #[test]
+    fn items_are_a_json_array_or_whitespace_separated() {
+        assert_eq!(parse_items(r#"["linux", "macos", 3, true]"#), Vec::from(["linux", "macos", "3", "true"]));
+        assert_eq!(parse_items("linux\n  macos\twindows "), Vec::from(["linux", "macos", "windows"]));
+        assert_eq!(parse_items(r#"["a b", "c\"d"]"#), Vec::from(["a b", "c\"d"]));
+        assert!(parse_items("[]").is_empty());
+        assert!(parse_items("").is_empty());
+    }
+
+    #[test]
+    fn id_part_only_keeps_folder_name_characters() {
+        assert_eq!(to_id_part("gcc-13.2_x86"), "gcc-13.2_x86");
+        assert_eq!(to_id_part("src/main file"), "src_main_file");
+    }
+
+    #[test]
+    fn each_item_gets_its_own_step() {
+        let steps = expand_step("linux macos").unwrap();
+        let ids: Vec<&str> = steps.iter().map(|(step_id, _)| step_id.as_str()).collect();
+        assert_eq!(ids, Vec::from(["//targets:build/0/linux", "//targets:build/0/macos"]));
+        assert_eq!(steps[1].1.replacements["item"], "macos");
+        assert_eq!(steps[1].1.replacements[ITEM_INDEX], "1");
+    }
+
+    #[test]
+    fn items_with_the_same_id_are_an_error() {
+        let error = expand_step("a/b a_b").unwrap_err();
+        assert!(error.contains("//targets:build/0/a_b"), "{}", error);
+    }
+}
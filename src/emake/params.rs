@@ -0,0 +1,202 @@
+use std::{collections::BTreeMap, sync::OnceLock};
+
+use crate::console::log;
+
+// A target with a `matrix` is built once per combination of the values of its matrix:
+//   matrix: { arch: [x86_64, aarch64], profile: [debug, release] }
+// gives the concrete targets `//targets:build[arch=x86_64,profile=debug]`... Building `build` builds
+// every combination, `build[arch=x86_64]` only the combinations with this arch.
+// The parameters of a target, and those given with `--param key=value`, are used in its steps like `{{ arch }}`.
+
+/// Parameters of a target, sorted so a target has a single path
+pub type Params = BTreeMap<String, String>;
+
+static CLI_PARAMS: OnceLock<Params> = OnceLock::new();
+
+fn is_param_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-'))
+}
+
+/// A value can't contain the separators of a target path
+fn is_param_value(value: &str) -> bool {
+    !value.is_empty() && !value.contains([',', '=', '[', ']', '/', ':'])
+}
+
+fn parse_param(param: &str) -> Result<(String, String), String> {
+    let Some((key, value)) = param.split_once('=') else {
+        return Err(format!("Invalid parameter `{}`, expected key=value", param));
+    };
+    let (key, value) = (key.trim(), value.trim());
+    if !is_param_key(key) {
+        return Err(format!("Invalid parameter name `{}`, only letters, digits, `_` and `-` are allowed", key));
+    }
+    if !is_param_value(value) {
+        return Err(format!("Invalid value `{}` of the parameter {}, it can't be empty nor contain , = [ ] / :", value, key));
+    }
+    Ok((String::from(key), String::from(value)))
+}
+
+/// Parameters given with `--param key=value` on the command line, stop emake when one is invalid
+pub fn set_cli_params(cli_params: &Vec<String>) {
+    let mut params = Params::new();
+    for param in cli_params {
+        match parse_param(param) {
+            Ok((key, value)) => {
+                params.insert(key, value);
+            }
+            Err(error) => {
+                log::panic!("{}", error);
+            }
+        }
+    }
+    let _ = CLI_PARAMS.set(params);
+}
+
+pub fn get_cli_params() -> Params {
+    CLI_PARAMS.get().cloned().unwrap_or_default()
+}
+
+/// Split `//targets:build[arch=x86_64,profile=release]` into the target path and its parameters
+pub fn split_target_path(target_path: &str) -> Result<(String, Params), String> {
+    let Some((base_path, rest)) = target_path.split_once('[') else {
+        return Ok((String::from(target_path), Params::new()));
+    };
+    let Some(params_list) = rest.strip_suffix(']') else {
+        return Err(format!("Missing `]` at the end of the target {}", target_path));
+    };
+
+    let mut params = Params::new();
+    for param in params_list.split(',').filter(|param| !param.trim().is_empty()) {
+        let (key, value) = parse_param(param)?;
+        if params.insert(key.clone(), value).is_some() {
+            return Err(format!("The parameter {} is given twice", key));
+        }
+    }
+    Ok((String::from(base_path), params))
+}
+
+pub fn to_target_path(base_path: &str, params: &Params) -> String {
+    if params.is_empty() {
+        return String::from(base_path);
+    }
+
+    let params_list: Vec<String> = params.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+    format!("{}[{}]", base_path, params_list.join(","))
+}
+
+/// Target path with its parameters sorted, an invalid path is kept to be reported when visited
+pub fn canonical_target_path(target_path: &str) -> String {
+    match split_target_path(target_path) {
+        Ok((base_path, params)) => to_target_path(&base_path, &params),
+        Err(_) => String::from(target_path),
+    }
+}
+
+/// Combinations of the values of a matrix, the keys given in `fixed` only take the given value
+pub fn expand_matrix(matrix: &BTreeMap<String, Vec<String>>, fixed: &Params) -> Result<Vec<Params>, String> {
+    if let Some(key) = fixed.keys().find(|key| !matrix.contains_key(*key)) {
+        return Err(format!(
+            "Unknown parameter {}, the parameters of the matrix are {}",
+            key,
+            matrix.keys().cloned().collect::<Vec<String>>().join(", ")
+        ));
+    }
+
+    let mut combinations = Vec::from([Params::new()]);
+    for (key, values) in matrix {
+        if let Some(value) = values.iter().find(|value| !is_param_value(value)) {
+            return Err(format!("Invalid value `{}` of {} in the matrix, it can't be empty nor contain , = [ ] / :", value, key));
+        }
+
+        let values = match fixed.get(key) {
+            Some(value) if values.contains(value) => Vec::from([value.clone()]),
+            Some(value) => {
+                return Err(format!("The value {} of {} is not in the matrix ({})", value, key, values.join(", ")));
+            }
+            None => values.clone(),
+        };
+
+        combinations = combinations
+            .iter()
+            .flat_map(|combination| {
+                values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.insert(key.clone(), value.clone());
+                    combination
+                })
+            })
+            .collect();
+    }
+
+    Ok(combinations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> Params {
+        pairs.iter().map(|(key, value)| (String::from(*key), String::from(*value))).collect()
+    }
+
+    fn matrix() -> BTreeMap<String, Vec<String>> {
+        BTreeMap::from([
+            (String::from("arch"), Vec::from([String::from("x86_64"), String::from("aarch64")])),
+            (String::from("profile"), Vec::from([String::from("debug"), String::from("release")])),
+        ])
+    }
+
+    #[test]
+    fn target_path_is_split_into_its_params() {
+        let (base_path, target_params) = split_target_path("//targets:build[profile=release, arch=x86_64]").unwrap();
+        assert_eq!(base_path, "//targets:build");
+        assert_eq!(target_params, params(&[("arch", "x86_64"), ("profile", "release")]));
+
+        let (base_path, target_params) = split_target_path("//targets:build").unwrap();
+        assert_eq!(base_path, "//targets:build");
+        assert!(target_params.is_empty());
+    }
+
+    #[test]
+    fn bad_params_syntax_is_an_error() {
+        assert!(split_target_path("//targets:build[arch=x86_64").is_err());
+        assert!(split_target_path("//targets:build[arch]").is_err());
+        assert!(split_target_path("//targets:build[arch=]").is_err());
+        assert!(split_target_path("//targets:build[=x86_64]").is_err());
+        assert!(split_target_path("//targets:build[arch=x86/64]").is_err());
+        assert!(split_target_path("//targets:build[arch=x86_64,arch=aarch64]").is_err());
+    }
+
+    #[test]
+    fn canonical_target_path_sorts_the_params() {
+        assert_eq!(canonical_target_path("//targets:build[profile=release,arch=x86_64]"), "//targets:build[arch=x86_64,profile=release]");
+        assert_eq!(canonical_target_path("//targets:build[]"), "//targets:build");
+        assert_eq!(canonical_target_path("//targets:build[arch"), "//targets:build[arch");
+    }
+
+    #[test]
+    fn matrix_is_expanded_to_every_combination() {
+        let combinations = expand_matrix(&matrix(), &Params::new()).unwrap();
+        assert_eq!(combinations.len(), 4);
+        assert!(combinations.contains(&params(&[("arch", "aarch64"), ("profile", "release")])));
+
+        let combinations = expand_matrix(&matrix(), &params(&[("arch", "x86_64")])).unwrap();
+        assert_eq!(
+            combinations,
+            Vec::from([params(&[("arch", "x86_64"), ("profile", "debug")]), params(&[("arch", "x86_64"), ("profile", "release")])])
+        );
+    }
+
+    #[test]
+    fn values_not_in_the_matrix_are_errors() {
+        let error = expand_matrix(&matrix(), &params(&[("arch", "riscv64")])).unwrap_err();
+        assert!(error.contains("not in the matrix"), "{}", error);
+
+        let error = expand_matrix(&matrix(), &params(&[("os", "linux")])).unwrap_err();
+        assert!(error.contains("Unknown parameter os"), "{}", error);
+
+        let mut invalid_matrix = matrix();
+        invalid_matrix.insert(String::from("os"), Vec::from([String::from("linux,macos")]));
+        assert!(expand_matrix(&invalid_matrix, &Params::new()).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::utils::parse_duration;
+
+// `timeout: 10m` fails a step running for too long, its processes are killed.
+// `retry: { attempts: 3, delay: 5s, backoff: 2, on_exit_codes: [128] }` runs a failed step again,
+// waiting 5s then 10s between the attempts. Timeouts are retried too.
+
+/// Longest wait between two attempts, whatever the backoff
+const MAX_DELAY: Duration = Duration::from_secs(24 * 3600);
+
+/// Read a duration written as a number of seconds or as a text like `1h30m`
+pub fn duration_from_value(value: &serde_yml::Value) -> Result<Duration, String> {
+    match value {
+        serde_yml::Value::Number(number) => match number.as_u64() {
+            Some(seconds) => Ok(Duration::from_secs(seconds)),
+            None => Err(format!("Invalid duration {}, expected a number of seconds or a duration like 10m", number)),
+        },
+        serde_yml::Value::String(text) => parse_duration(text),
+        _ => Err(String::from("Invalid duration, expected a number of seconds or a duration like 10m")),
+    }
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = serde_yml::Value::deserialize(deserializer)?;
+    duration_from_value(&value).map(Some).map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Number of runs of the step at most, the first one included
+    pub attempts: u32,
+    /// Wait before running the step again, nothing by default
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub delay: Option<Duration>,
+    /// The delay is multiplied by this factor after each attempt
+    pub backoff: Option<f64>,
+    /// Retry only the commands exiting with one of these codes, any failure by default
+    pub on_exit_codes: Option<Vec<i32>>,
+}
+
+impl RetryPolicy {
+    pub fn check(&self) -> Result<(), String> {
+        if self.attempts == 0 {
+            return Err(String::from("The retry attempts must be at least 1"));
+        }
+        if self.backoff.is_some_and(|backoff| backoff.is_nan() || backoff < 1.0) {
+            return Err(String::from("The retry backoff must be at least 1"));
+        }
+        Ok(())
+    }
+
+    /// Wait before the attempt following the given one, starting at 1, at most `MAX_DELAY`
+    pub fn get_delay(&self, attempt: u32) -> Duration {
+        let delay = self.delay.unwrap_or(Duration::ZERO).min(MAX_DELAY);
+        if delay.is_zero() {
+            return Duration::ZERO;
+        }
+        let factor = self.backoff.unwrap_or(1.0).powi(attempt.saturating_sub(1).try_into().unwrap_or(i32::MAX));
+        Duration::try_from_secs_f64(delay.as_secs_f64() * factor)
+            .unwrap_or(MAX_DELAY)
+            .min(MAX_DELAY)
+    }
+
+    /// Whether a failure is worth another attempt, a timeout always is
+    pub fn should_retry(&self, exit_code: Option<i32>, timed_out: bool) -> bool {
+        match &self.on_exit_codes {
+            _ if timed_out => true,
+            Some(on_exit_codes) => exit_code.is_some_and(|exit_code| on_exit_codes.contains(&exit_code)),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{duration_from_value, RetryPolicy, MAX_DELAY};
+
+    fn retry_policy(delay: Duration, backoff: f64) -> RetryPolicy {
+        RetryPolicy {
+            attempts: 40,
+            delay: Some(delay),
+            backoff: Some(backoff),
+            on_exit_codes: None,
+        }
+    }
+
+    #[test]
+    fn delay_grows_with_the_backoff() {
+        let retry = retry_policy(Duration::from_secs(5), 2.0);
+        assert_eq!(retry.get_delay(1), Duration::from_secs(5));
+        assert_eq!(retry.get_delay(3), Duration::from_secs(20));
+    }
+
+    #[test]
+    fn delay_saturates_instead_of_overflowing() {
+        assert_eq!(retry_policy(Duration::from_secs(5), 10.0).get_delay(39), MAX_DELAY);
+        assert_eq!(retry_policy(Duration::from_secs(5), f64::INFINITY).get_delay(2), MAX_DELAY);
+        assert_eq!(retry_policy(Duration::ZERO, f64::INFINITY).get_delay(2), Duration::ZERO);
+    }
+
+    #[test]
+    fn backoff_below_one_or_nan_is_invalid() {
+        assert!(retry_policy(Duration::ZERO, 0.5).check().is_err());
+        assert!(retry_policy(Duration::ZERO, f64::NAN).check().is_err());
+        assert!(retry_policy(Duration::ZERO, 1.0).check().is_ok());
+    }
+
+    #[test]
+    fn too_long_durations_are_errors() {
+        let parse = |text: &str| duration_from_value(&serde_yml::Value::String(String::from(text)));
+        assert_eq!(parse("1h30m"), Ok(Duration::from_secs(5400)));
+        assert!(parse("99999999999999999h").is_err());
+        assert!(parse("18446744073709551615s1s").is_err());
+    }
+}
@@ -1,7 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+pub mod dag;
 pub mod generator;
+pub mod profiler;
 pub mod runner;
+pub mod scheduler;
 pub mod viewer;
 pub mod common;
 
@@ -0,0 +1,559 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use crate::{
+    console::log,
+    emake::{self, condition::Condition, env::EnvConfig, for_each, loader::extract_info_from_path, params, ChangeDetection, Emakefile, Step, Target},
+    graph::{
+        common::is_downloadable_file,
+        generator::{get_absolute_target_path, to_emakefile_path},
+        runner::{get_compiled_in_files, get_default_replacements, get_real_out_files, has_dynamic_in_files},
+    },
+    get_cwd,
+    utils::get_absolute_file_path,
+};
+
+pub struct StepNode {
+    pub id: String,
+    pub target_path: String,
+    pub emakefile_path: String,
+    pub step: Step,
+    pub change_detection: ChangeDetection,
+    /// Conditions of the target then of the step, the step is skipped when one is false
+    pub conditions: Vec<Condition>,
+    /// Environment merged from the Emakefile, the target and the step, compiled when the step runs
+    pub env: EnvConfig,
+    /// Indexes of the steps which must be done before this one, filled by `link_steps`
+    pub deps: Vec<usize>,
+    /// Later steps reading each out file of this one, filled by `link_steps`
+    pub out_file_readers: OutFileReaders,
+}
+
+pub type OutFileReaders = HashMap<PathBuf, Vec<Arc<StepOutputs>>>;
+
+/// Out files of a step reading the out file of an earlier step.
+/// A missing out file doesn't make its step run again when a step reading it is up to date,
+/// this step may have moved or removed it.
+pub struct StepOutputs {
+    pub step_id: String,
+    pub out_files: Vec<String>,
+    pub change_detection: ChangeDetection,
+    pub out_file_readers: OutFileReaders,
+}
+
+pub struct TargetNode {
+    pub target: Target,
+    /// Absolute paths of the dependencies
+    pub deps: Vec<String>,
+    /// Targets which must be built before this one because of a `parallel_deps: false`
+    pub after: Vec<String>,
+    /// Indexes of the steps of the target in `BuildGraph::steps`
+    pub steps: Vec<usize>,
+}
+
+/// Targets reachable from the built targets, checked before any step runs
+pub struct BuildGraph {
+    pub targets: HashMap<String, TargetNode>,
+    /// Target paths sorted so dependencies come before the targets using them
+    pub order: Vec<String>,
+    /// Steps of all the targets, following the order of the targets.
+    /// A step only depends on steps placed before it, so the steps graph can't contain a cycle.
+    pub steps: Vec<StepNode>,
+}
+
+pub enum GraphError {
+    Cycle(Vec<String>),
+    MissingEmakefile {
+        target: String,
+        emakefile_path: String,
+        referenced_by: Option<String>,
+    },
+    MissingTarget {
+        target: String,
+        emakefile_path: String,
+        referenced_by: Option<String>,
+    },
+    InvalidCondition {
+        id: String,
+        condition: String,
+        error: String,
+    },
+    InvalidForEach {
+        id: String,
+        for_each: String,
+        error: String,
+    },
+    InvalidParams {
+        target: String,
+        error: String,
+    },
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let referenced_by = |referenced_by: &Option<String>| match referenced_by {
+            Some(parent) => format!(", required by {}", parent),
+            None => String::new(),
+        };
+
+        match self {
+            GraphError::Cycle(path) => write!(f, "Dependency cycle found: {}", path.join(" -> ")),
+            GraphError::MissingEmakefile {
+                target,
+                emakefile_path,
+                referenced_by: parent,
+            } => write!(
+                f,
+                "Target {} not found, the Emakefile {} doesn't exist{}",
+                target,
+                emakefile_path,
+                referenced_by(parent)
+            ),
+            GraphError::MissingTarget {
+                target,
+                emakefile_path,
+                referenced_by: parent,
+            } => write!(
+                f,
+                "Target {} not found in the Emakefile {}{}",
+                target,
+                emakefile_path,
+                referenced_by(parent)
+            ),
+            GraphError::InvalidCondition { id, condition, error } => {
+                write!(f, "Invalid condition `{}` in {}: {}", condition, id, error)
+            }
+            GraphError::InvalidForEach { id, for_each, error } => {
+                write!(f, "Invalid for_each `{}` in {}: {}", for_each, id, error)
+            }
+            GraphError::InvalidParams { target, error } => write!(f, "Invalid parameters of the target {}: {}", target, error),
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum VisitState {
+    InProgress,
+    Done,
+}
+
+struct GraphBuilder {
+    emakefiles: HashMap<String, Option<Emakefile>>,
+    states: HashMap<String, VisitState>,
+    stack: Vec<String>,
+    graph: BuildGraph,
+    errors: Vec<GraphError>,
+    /// Change detection of the root Emakefile, used by the Emakefiles which don't define their own
+    root_change_detection: ChangeDetection,
+}
+
+impl GraphBuilder {
+    fn get_emakefile(&mut self, emakefile_path: &String) -> Option<&Emakefile> {
+        self.emakefiles
+            .entry(emakefile_path.clone())
+            .or_insert_with(|| {
+                if std::path::Path::new(emakefile_path).exists() {
+                    Some(emake::loader::load_file(emakefile_path))
+                } else {
+                    None
+                }
+            })
+            .as_ref()
+    }
+
+    fn parse_condition(&mut self, id: &str, maybe_condition: &Option<String>) -> Option<Condition> {
+        let condition = maybe_condition.as_ref()?;
+        match Condition::parse(condition) {
+            Ok(condition) => Some(condition),
+            Err(error) => {
+                self.errors.push(GraphError::InvalidCondition {
+                    id: String::from(id),
+                    condition: condition.clone(),
+                    error,
+                });
+                None
+            }
+        }
+    }
+
+    fn visit(&mut self, target_path: &String, referenced_by: Option<&String>) {
+        match self.states.get(target_path) {
+            Some(VisitState::Done) => return,
+            Some(VisitState::InProgress) => {
+                let cycle_start = self.stack.iter().position(|path| path == target_path).unwrap();
+                let mut cycle = self.stack[cycle_start..].to_vec();
+                cycle.push(target_path.clone());
+                self.errors.push(GraphError::Cycle(cycle));
+                return;
+            }
+            None => {}
+        }
+
+        let (base_target_path, path_params) = match params::split_target_path(target_path) {
+            Ok(split_path) => split_path,
+            Err(error) => {
+                self.errors.push(GraphError::InvalidParams {
+                    target: target_path.clone(),
+                    error,
+                });
+                self.states.insert(target_path.clone(), VisitState::Done);
+                return;
+            }
+        };
+
+        let emakefile_path = to_emakefile_path(&base_target_path).to_string_lossy().to_string();
+        let target_name = extract_info_from_path(&base_target_path, &emakefile_path)
+            .map(|target_info| target_info.target_name)
+            .unwrap_or_default();
+
+        let Some(emakefile) = self.get_emakefile(&emakefile_path) else {
+            self.errors.push(GraphError::MissingEmakefile {
+                target: target_path.clone(),
+                emakefile_path,
+                referenced_by: referenced_by.cloned(),
+            });
+            self.states.insert(target_path.clone(), VisitState::Done);
+            return;
+        };
+
+        let Some(mut target) = emakefile.targets.get(&target_name).cloned() else {
+            self.errors.push(GraphError::MissingTarget {
+                target: target_path.clone(),
+                emakefile_path,
+                referenced_by: referenced_by.cloned(),
+            });
+            self.states.insert(target_path.clone(), VisitState::Done);
+            return;
+        };
+
+        if let Some(matrix) = &target.matrix {
+            // The parameters of the command line select values of the matrix too
+            let mut fixed_params = params::get_cli_params();
+            fixed_params.retain(|key, _value| matrix.contains_key(key));
+            fixed_params.extend(path_params.clone());
+
+            match params::expand_matrix(matrix, &fixed_params) {
+                // Without all its parameters, the target stands for the matching concrete targets
+                Ok(combinations) if path_params.len() < matrix.len() => {
+                    target.deps = Some(
+                        combinations
+                            .iter()
+                            .map(|combination| params::to_target_path(&base_target_path, combination))
+                            .collect(),
+                    );
+                    target.when = None;
+                    target.steps = None;
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    self.errors.push(GraphError::InvalidParams {
+                        target: target_path.clone(),
+                        error,
+                    });
+                    self.states.insert(target_path.clone(), VisitState::Done);
+                    return;
+                }
+            }
+        }
+
+        // Parameters of the command line apply to every target, those of the target path win
+        let mut target_params = params::get_cli_params();
+        target_params.extend(path_params);
+        let mut replacements = get_default_replacements(&emakefile_path);
+        replacements.extend(target_params.clone());
+
+        let deps: Vec<String> = target
+            .deps
+            .iter()
+            .flatten()
+            .map(|dep| {
+                // A dependency can use the parameters, like `lib[arch={{ arch }}]`
+                let dep = match dep.contains("{{") {
+                    true => emake::compiler::compile(dep, &emakefile_path, Some(&replacements), None),
+                    false => dep.clone(),
+                };
+                params::canonical_target_path(&get_absolute_target_path(&dep, &emakefile_path))
+            })
+            .collect();
+
+        self.states.insert(target_path.clone(), VisitState::InProgress);
+        self.stack.push(target_path.clone());
+        for dep in &deps {
+            self.visit(dep, Some(target_path));
+        }
+        self.stack.pop();
+        self.states.insert(target_path.clone(), VisitState::Done);
+
+        // Dependencies of a sequential list are built one after the other,
+        // unless an earlier one already depends on a later one
+        if !target.parallel_deps.unwrap_or(true) {
+            for pair in deps.windows(2) {
+                let previous_position = self.graph.order.iter().position(|path| *path == pair[0]);
+                let next_position = self.graph.order.iter().position(|path| *path == pair[1]);
+                if let (Some(previous_position), Some(next_position)) = (previous_position, next_position) {
+                    if previous_position < next_position {
+                        self.graph.targets.get_mut(&pair[1]).unwrap().after.push(pair[0].clone());
+                    }
+                }
+            }
+        }
+
+        let root_change_detection = self.root_change_detection;
+        let emakefile = self.get_emakefile(&emakefile_path).unwrap();
+        let change_detection = emakefile.change_detection.unwrap_or(root_change_detection);
+        let target_env = emakefile.env.merge(&target.env);
+        let target_conditions: Vec<Condition> = self.parse_condition(target_path, &target.when).into_iter().collect();
+        let mut steps = Vec::new();
+        for (step_index, step) in target.steps.iter().flatten().enumerate() {
+            let step_id = format!("{}/{}", target_path, step_index);
+            let mut conditions = target_conditions.clone();
+            conditions.extend(self.parse_condition(&step_id, &step.when));
+
+            let mut step = step.clone();
+            step.replacements.extend(target_params.clone());
+            let expanded_steps = match for_each::expand(&step_id, &step, &emakefile_path, &replacements) {
+                Ok(expanded_steps) => expanded_steps,
+                Err(error) => {
+                    self.errors.push(GraphError::InvalidForEach {
+                        id: step_id,
+                        for_each: step.for_each.clone().unwrap_or_default(),
+                        error,
+                    });
+                    continue;
+                }
+            };
+
+            for (expanded_step_id, mut expanded_step) in expanded_steps {
+                // The parameters and items tell the steps apart in the logs
+                if !expanded_step.replacements.is_empty() {
+                    let mut step_replacements = replacements.clone();
+                    step_replacements.extend(expanded_step.replacements.clone());
+                    expanded_step.description =
+                        emake::compiler::compile(&expanded_step.description, &emakefile_path, Some(&step_replacements), None);
+                }
+
+                steps.push(self.graph.steps.len());
+                self.graph.steps.push(StepNode {
+                    id: expanded_step_id,
+                    target_path: target_path.clone(),
+                    emakefile_path: emakefile_path.clone(),
+                    step: expanded_step,
+                    change_detection,
+                    conditions: conditions.clone(),
+                    env: target_env.merge(&step.env),
+                    deps: Vec::new(),
+                    out_file_readers: HashMap::new(),
+                });
+            }
+        }
+
+        self.graph.order.push(target_path.clone());
+        self.graph.targets.insert(
+            target_path.clone(),
+            TargetNode {
+                target,
+                deps,
+                after: Vec::new(),
+                steps,
+            },
+        );
+    }
+}
+
+struct StepFiles {
+    in_files: Vec<PathBuf>,
+    out_files: Vec<PathBuf>,
+    /// The in_files are resolved before the previous steps write them, they can't order the step
+    dynamic_in_files: bool,
+}
+
+impl StepFiles {
+    fn is_empty(&self) -> bool {
+        self.in_files.is_empty() && self.out_files.is_empty()
+    }
+}
+
+impl BuildGraph {
+    /// Steps to wait for before starting the steps of a target depending on the given one
+    fn get_target_steps(&self, target_path: &String, visited: &mut HashSet<String>) -> Vec<usize> {
+        if !visited.insert(target_path.clone()) {
+            return Vec::new();
+        }
+
+        let target = &self.targets[target_path];
+        if !target.steps.is_empty() {
+            // The steps of the target already wait for its own dependencies
+            return target.steps.clone();
+        }
+
+        let mut steps = Vec::new();
+        for dep in target.deps.iter().chain(target.after.iter()) {
+            steps.extend(self.get_target_steps(dep, visited));
+        }
+        steps
+    }
+
+    /// Compute the dependencies of every step.
+    /// A step waits for the dependencies of its target, and for any earlier step
+    /// writing one of its in_files, or writing in the same place than its out_files.
+    /// The in_files are resolved before any step runs, a step with a glob in its in_files
+    /// waits for all the previous steps of its target.
+    pub async fn link_steps(&mut self) {
+        let mut files = Vec::new();
+        for node in &self.steps {
+            let in_files = get_compiled_in_files(&node.step, &node.emakefile_path)
+                .await
+                .into_iter()
+                .filter(|(file, _credentials)| !is_downloadable_file(file))
+                .map(|(file, _credentials)| get_absolute_file_path(&file))
+                .collect();
+            let out_files = get_real_out_files(&node.id, &node.step, &node.emakefile_path)
+                .await
+                .iter()
+                .map(|file| get_absolute_file_path(file))
+                .collect();
+            let dynamic_in_files = has_dynamic_in_files(&node.step, &node.emakefile_path).await;
+            files.push(StepFiles {
+                in_files,
+                out_files,
+                dynamic_in_files,
+            });
+        }
+
+        // Steps writing each out file, a folder can be written by a step and read by another one
+        let mut writers: BTreeMap<PathBuf, Vec<usize>> = BTreeMap::new();
+        // Later steps reading each out file of a step
+        let mut readers: Vec<BTreeMap<PathBuf, BTreeSet<usize>>> = vec![BTreeMap::new(); self.steps.len()];
+
+        for step_index in 0..self.steps.len() {
+            let mut deps = BTreeSet::new();
+            let target = &self.targets[&self.steps[step_index].target_path];
+
+            let mut visited = HashSet::new();
+            for dep in target.deps.iter().chain(target.after.iter()) {
+                deps.extend(self.get_target_steps(dep, &mut visited));
+            }
+
+            let position = target.steps.iter().position(|index| *index == step_index).unwrap();
+            let previous_steps = &target.steps[..position];
+            match target.target.parallel_steps {
+                Some(false) => deps.extend(previous_steps.last()),
+                Some(true) => {}
+                None => {
+                    // Steps without files, or reading files not known yet, can't be ordered by their files,
+                    // they keep their place
+                    if files[step_index].is_empty() || files[step_index].dynamic_in_files {
+                        deps.extend(previous_steps);
+                    } else {
+                        deps.extend(previous_steps.iter().filter(|index| files[**index].is_empty()));
+                    }
+                }
+            }
+
+            let step_files = &files[step_index];
+            for in_file in &step_files.in_files {
+                for ancestor in in_file.ancestors() {
+                    for writer_index in writers.get(ancestor).into_iter().flatten() {
+                        readers[*writer_index].entry(ancestor.to_path_buf()).or_default().insert(step_index);
+                    }
+                }
+            }
+            for file in step_files.in_files.iter().chain(step_files.out_files.iter()) {
+                // Written by an earlier step, or inside a folder written by an earlier step
+                for ancestor in file.ancestors() {
+                    if let Some(writer_indexes) = writers.get(ancestor) {
+                        deps.extend(writer_indexes);
+                    }
+                }
+
+                // Written by an earlier step inside the folder
+                for (_path, writer_indexes) in writers
+                    .range(file.clone()..)
+                    .take_while(|(path, _writer_indexes)| path.starts_with(file))
+                {
+                    deps.extend(writer_indexes);
+                }
+            }
+
+            for file in &step_files.out_files {
+                writers.entry(file.clone()).or_default().push(step_index);
+            }
+
+            deps.remove(&step_index);
+            self.steps[step_index].deps = deps.into_iter().collect();
+        }
+
+        // Readers come after the steps they read, their outputs are built from the last step
+        let mut outputs: Vec<Option<Arc<StepOutputs>>> = vec![None; self.steps.len()];
+        for step_index in (0..self.steps.len()).rev() {
+            let out_file_readers: OutFileReaders = readers[step_index]
+                .iter()
+                .map(|(file, reader_indexes)| {
+                    let reader_outputs = reader_indexes.iter().filter_map(|index| outputs[*index].clone()).collect();
+                    (file.clone(), reader_outputs)
+                })
+                .collect();
+            let node = &mut self.steps[step_index];
+            outputs[step_index] = Some(Arc::new(StepOutputs {
+                step_id: node.id.clone(),
+                out_files: files[step_index].out_files.iter().map(|file| file.to_string_lossy().to_string()).collect(),
+                change_detection: node.step.change_detection.unwrap_or(node.change_detection),
+                out_file_readers: out_file_readers.clone(),
+            }));
+            node.out_file_readers = out_file_readers;
+        }
+    }
+}
+
+/// Load all the targets reachable from the given targets and check the graph is valid
+pub fn build(target_paths: &Vec<String>) -> Result<BuildGraph, Vec<GraphError>> {
+    let mut builder = GraphBuilder {
+        emakefiles: HashMap::new(),
+        states: HashMap::new(),
+        stack: Vec::new(),
+        graph: BuildGraph {
+            targets: HashMap::new(),
+            order: Vec::new(),
+            steps: Vec::new(),
+        },
+        errors: Vec::new(),
+        root_change_detection: ChangeDetection::default(),
+    };
+
+    // Loaded once for the whole graph, then kept with the other Emakefiles
+    let root_emakefile_path = get_cwd().join("Emakefile").to_string_lossy().to_string();
+    if let Some(change_detection) = builder.get_emakefile(&root_emakefile_path).and_then(|emakefile| emakefile.change_detection) {
+        builder.root_change_detection = change_detection;
+    }
+
+    for target_path in target_paths {
+        builder.visit(&params::canonical_target_path(target_path), None);
+    }
+
+    if builder.errors.is_empty() {
+        Ok(builder.graph)
+    } else {
+        Err(builder.errors)
+    }
+}
+
+/// Same as build, but report the errors and stop emake when the graph is invalid
+pub fn load(target_paths: &Vec<String>) -> BuildGraph {
+    match build(target_paths) {
+        Ok(graph) => graph,
+        Err(errors) => {
+            for error in &errors {
+                log::error!("{}", error);
+            }
+            log::panic!(
+                "The build graph is invalid, {} {} found",
+                errors.len(),
+                if errors.len() > 1 { "errors" } else { "error" }
+            );
+        }
+    }
+}
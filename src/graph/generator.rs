@@ -5,10 +5,95 @@ use serde_yml::Value;
 use std::path::Path;
 use std::path::PathBuf;
 
-use crate::cache;
+use walkdir::WalkDir;
+
+use crate::emake;
 use crate::get_cwd;
 use crate::graph::InFile;
 
+/// Support the `dir:target` shorthand, where the part before `:` is a directory instead of `targets`
+fn push_target_directory<'a>(path_parts: &mut Vec<&'a str>, target_key_parts: &Vec<&'a str>) {
+    if let Some(directory) = target_key_parts.first() {
+        if *directory != "targets" && !directory.is_empty() {
+            path_parts.push(directory);
+        }
+    }
+}
+
+/// Target patterns select targets of all the Emakefiles below a directory, like `//services/...:test`
+pub fn is_target_pattern(path: &str) -> bool {
+    path.split('/').any(|part| part == "..." || part.starts_with("...:"))
+}
+
+/// Resolve a target path or a target pattern into absolute target paths
+pub fn expand_target_path(path: &String, emakefile_current_path: &String) -> Vec<String> {
+    if !is_target_pattern(path) {
+        return Vec::from([get_absolute_target_path(path, emakefile_current_path)]);
+    }
+
+    let cwd = get_cwd();
+    let (base, selector) = path.split_once("...").unwrap();
+    let selector = selector.trim_start_matches(':');
+    let selector = selector.strip_prefix("targets:").unwrap_or(selector);
+
+    let base_dir = if base.starts_with("//") {
+        cwd.join(base.trim_matches('/'))
+    } else {
+        Path::new(emakefile_current_path)
+            .parent()
+            .unwrap()
+            .join(base.trim_matches('/'))
+    };
+
+    let mut target_paths = Vec::new();
+    for emakefile_path in find_emakefiles(&base_dir) {
+        let emakefile_path = emakefile_path.to_string_lossy().to_string();
+        let emakefile = emake::loader::load_file(&emakefile_path);
+
+        let mut target_names: Vec<&String> = emakefile
+            .targets
+            .keys()
+            .filter(|target_name| selector.is_empty() || *target_name == selector)
+            .collect();
+        target_names.sort();
+
+        for target_name in target_names {
+            target_paths.push(to_absolute_entity_path(&emakefile_path, "targets", target_name));
+        }
+    }
+
+    target_paths
+}
+
+/// Find all the Emakefiles below a directory, hidden directories are ignored
+pub fn find_emakefiles(base_dir: &Path) -> Vec<PathBuf> {
+    WalkDir::new(base_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && entry.file_name() == "Emakefile")
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+/// Absolute path of an entity declared in an Emakefile, like `//sub/dir/targets:name`
+pub fn to_absolute_entity_path(emakefile_path: &String, entity_type: &str, entity_name: &str) -> String {
+    let cwd = get_cwd();
+    let emakefile_dir = Path::new(emakefile_path).parent().unwrap();
+    let relative_dir = emakefile_dir
+        .strip_prefix(&cwd)
+        .unwrap_or(emakefile_dir)
+        .to_string_lossy()
+        .to_string();
+
+    if relative_dir.is_empty() {
+        format!("//{}:{}", entity_type, entity_name)
+    } else {
+        format!("//{}/{}:{}", relative_dir, entity_type, entity_name)
+    }
+}
+
 pub fn get_absolute_target_path(
     path: &String,
     emakefile_current_path: &String,
@@ -20,6 +105,7 @@ pub fn get_absolute_target_path(
         let mut target_key = path_parts.pop().unwrap();
         let mut target_key_parts: Vec<&str> = target_key.split(':').collect();
         target_key = target_key_parts.pop().unwrap();
+        push_target_directory(&mut path_parts, &target_key_parts);
 
         path_parts.join(&path_separator) + "/targets:" + target_key
     } else {
@@ -30,6 +116,7 @@ pub fn get_absolute_target_path(
         let target_key = path_parts.pop().unwrap();
         let mut target_key_parts: Vec<&str> = target_key.split(':').collect();
         let target_key = target_key_parts.pop().unwrap();
+        push_target_directory(&mut path_parts, &target_key_parts);
         let parent_target_path = (path_separator
             + Path::new(emakefile_current_path)
                 .parent()
@@ -77,14 +164,6 @@ pub struct TargetProperties {
     pub actions: Vec<ActionProperties>,
 }
 
-pub fn to_footprint_path(target_absolute_path: &str) -> PathBuf {
-    let footprints_dir = cache::get_footprints_dir_path();
-    let footprint_path: String = target_absolute_path
-        .replace("targets:", "_targets_/")
-        .replace("//", "");
-    Path::new(&footprints_dir).join(footprint_path)
-}
-
 pub fn to_emakefile_path(target_absolute_path: &str) -> PathBuf {
     let cwd = get_cwd();
     let re = Regex::new(r"targets:.+$").unwrap();
@@ -0,0 +1,230 @@
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+
+use crate::{console::log, graph::dag::BuildGraph, utils::format_elapsed};
+
+// With `emake build --profile <path>`, the start and end of each target and step are recorded
+// to write a Chrome trace (chrome://tracing, https://ui.perfetto.dev) at the end of the build.
+const SLOWEST_STEPS_COUNT: usize = 10;
+
+static PROFILE_PATH: OnceLock<String> = OnceLock::new();
+static PROFILE_START: OnceLock<Instant> = OnceLock::new();
+static STEPS: Lazy<Mutex<Vec<Span>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static TARGETS: Lazy<Mutex<HashMap<String, Span>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// Steps of the build in topological order, with the steps they depend on
+static STEPS_DEPS: Lazy<Mutex<Vec<StepDeps>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+type StepDeps = (String, Vec<String>);
+
+#[derive(Debug, Clone)]
+struct Span {
+    name: String,
+    description: String,
+    start: Duration,
+    end: Duration,
+    /// Runs of a retried step
+    attempts: u32,
+}
+
+impl Span {
+    fn duration(&self) -> Duration {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+pub fn set_profile(path: &str) {
+    PROFILE_PATH.set(String::from(path)).ok();
+    PROFILE_START.get_or_init(Instant::now);
+}
+
+fn is_enabled() -> bool {
+    PROFILE_PATH.get().is_some()
+}
+
+fn since_start(instant: Instant) -> Duration {
+    instant.saturating_duration_since(*PROFILE_START.get_or_init(Instant::now))
+}
+
+pub fn record_graph(graph: &BuildGraph) {
+    if !is_enabled() {
+        return;
+    }
+
+    let mut steps_deps = STEPS_DEPS.lock().unwrap();
+    for node in &graph.steps {
+        let deps = node.deps.iter().map(|index| graph.steps[*index].id.clone()).collect();
+        steps_deps.push((node.id.clone(), deps));
+    }
+}
+
+pub fn record_step(step_id: &str, description: &str, start: Instant, end: Instant, attempts: u32) {
+    if !is_enabled() {
+        return;
+    }
+
+    STEPS.lock().unwrap().push(Span {
+        name: String::from(step_id),
+        description: String::from(description),
+        start: since_start(start),
+        end: since_start(end),
+        attempts,
+    });
+}
+
+pub fn record_target_start(target_path: &str) {
+    if !is_enabled() {
+        return;
+    }
+
+    let now = since_start(Instant::now());
+    TARGETS.lock().unwrap().insert(
+        String::from(target_path),
+        Span {
+            name: String::from(target_path),
+            description: String::new(),
+            start: now,
+            end: now,
+            attempts: 1,
+        },
+    );
+}
+
+pub fn record_target_end(target_path: &str) {
+    if !is_enabled() {
+        return;
+    }
+
+    if let Some(span) = TARGETS.lock().unwrap().get_mut(target_path) {
+        span.end = since_start(Instant::now());
+    }
+}
+
+/// Spread overlapping spans on several threads of the trace, like a build using several jobs
+fn to_trace_events(spans: &mut [Span], pid: u32, category: &str) -> Vec<Value> {
+    spans.sort_by_key(|span| span.start);
+
+    let mut lanes_end: Vec<Duration> = Vec::new();
+    let mut trace_events = Vec::new();
+    for span in spans.iter() {
+        let lane = match lanes_end.iter().position(|end| *end <= span.start) {
+            Some(lane) => lane,
+            None => {
+                lanes_end.push(Duration::ZERO);
+                lanes_end.len() - 1
+            }
+        };
+        lanes_end[lane] = span.end;
+
+        let mut args = json!({ "description": span.description });
+        if span.attempts > 1 {
+            args["attempts"] = json!(span.attempts);
+        }
+        trace_events.push(json!({
+            "name": span.name,
+            "cat": category,
+            "ph": "X",
+            "ts": span.start.as_micros() as u64,
+            "dur": span.duration().as_micros() as u64,
+            "pid": pid,
+            "tid": lane,
+            "args": args,
+        }));
+    }
+
+    trace_events
+}
+
+/// Longest chain of dependent steps, the build can't be faster than this chain
+fn get_critical_path(durations: &HashMap<String, Duration>) -> (Vec<String>, Duration) {
+    let steps_deps = STEPS_DEPS.lock().unwrap();
+    let mut path_durations: HashMap<&String, (Duration, Option<&String>)> = HashMap::new();
+
+    // Steps are in topological order, the dependencies of a step are already computed
+    for (step_id, deps) in steps_deps.iter() {
+        let mut longest_dep: (Duration, Option<&String>) = (Duration::ZERO, None);
+        for dep in deps {
+            if let Some((dep_duration, _previous)) = path_durations.get(dep) {
+                if *dep_duration > longest_dep.0 || longest_dep.1.is_none() {
+                    longest_dep = (*dep_duration, Some(dep));
+                }
+            }
+        }
+
+        let step_duration = durations.get(step_id).copied().unwrap_or_default();
+        path_durations.insert(step_id, (longest_dep.0 + step_duration, longest_dep.1));
+    }
+
+    let Some((last_step, (total, _previous))) = path_durations.iter().max_by_key(|(_step_id, (duration, _previous))| *duration) else {
+        return (Vec::new(), Duration::ZERO);
+    };
+
+    let mut path = Vec::new();
+    let mut current = Some(*last_step);
+    while let Some(step_id) = current {
+        path.push(step_id.clone());
+        current = path_durations[step_id].1;
+    }
+    path.reverse();
+
+    (path, *total)
+}
+
+fn print_summary(steps: &[Span]) {
+    let durations: HashMap<String, Duration> = steps
+        .iter()
+        .map(|span| (span.name.clone(), span.duration()))
+        .collect();
+
+    let (critical_path, critical_path_duration) = get_critical_path(&durations);
+    if !critical_path.is_empty() {
+        log::info!("\x1b[1mCritical path\x1b[0m ({})", format_elapsed(critical_path_duration));
+        // Skipped steps take no time, only the steps which ran are shown
+        for step_id in &critical_path {
+            if let Some(duration) = durations.get(step_id) {
+                log::info!("  {}  {}", step_id, format_elapsed(*duration));
+            }
+        }
+    }
+
+    let mut slowest_steps = steps.to_vec();
+    slowest_steps.sort_by_key(|span| std::cmp::Reverse(span.duration()));
+    slowest_steps.truncate(SLOWEST_STEPS_COUNT);
+    if !slowest_steps.is_empty() {
+        log::info!("\x1b[1m{} slowest steps\x1b[0m", slowest_steps.len());
+        for span in &slowest_steps {
+            log::info!("  {}  {}  {}", span.name, format_elapsed(span.duration()), span.description);
+        }
+    }
+}
+
+/// Write the Chrome trace and print the critical path, called once the build is over
+pub fn write_profile() {
+    let Some(path) = PROFILE_PATH.get() else {
+        return;
+    };
+
+    let mut steps = STEPS.lock().unwrap().clone();
+    let mut targets: Vec<Span> = TARGETS.lock().unwrap().values().cloned().collect();
+
+    let mut trace_events = Vec::from([
+        json!({ "name": "process_name", "ph": "M", "pid": 1, "args": { "name": "Targets" } }),
+        json!({ "name": "process_name", "ph": "M", "pid": 2, "args": { "name": "Steps" } }),
+    ]);
+    trace_events.extend(to_trace_events(&mut targets, 1, "target"));
+    trace_events.extend(to_trace_events(&mut steps, 2, "step"));
+
+    let trace = json!({ "traceEvents": trace_events, "displayTimeUnit": "ms" });
+    match fs::write(path, trace.to_string()) {
+        Ok(()) => log::info!("Profile written in {}", path),
+        Err(error) => log::warning!("Can't write the profile {}: {}", path, error),
+    }
+
+    print_summary(&steps);
+}
@@ -1,35 +1,52 @@
 use crate::actions::{
     compute_action_footprint, get_registered_action_footprint, register_action_footprint,
+    ActionContext, ActionError,
 };
 use crate::commands::build::update_progress;
+use crate::console::events::{self, Event};
 use crate::console::log::{self, StepStatus};
-use crate::emake::loader::{extract_info_from_path, Target, TargetType};
-use crate::emake::{Credentials, Step};
-use crate::graph::generator::{get_absolute_target_path, to_emakefile_path};
+use crate::emake::loader::{Target, TargetType};
+use crate::cache::state::{self, ImplicitFiles};
+use crate::emake::env::StepEnv;
+use crate::emake::{ChangeDetection, Credentials, Step};
+use crate::graph::dag::{BuildGraph, OutFileReaders, StepNode, StepOutputs};
+use crate::graph::{profiler, scheduler};
 use crate::utils::{format_elapsed, get_absolute_file_path};
 use crate::{
-    ACTIONS_STORE, CACHE_IN_FILE_TO_UPDATE, CACHE_OUT_FILE_TO_UPDATE, CREDENTIALS_STORE, cache, emake, get_cwd, get_mutex_for_id, graph, secrets, utils
+    ACTIONS_STORE, CACHE_IN_FILE_TO_UPDATE, CACHE_OUT_FILE_TO_UPDATE, CREDENTIALS_STORE, cache, emake, get_cwd, graph, remote_cache, secrets, step_logs, utils
 };
-use dashmap::DashMap;
-use futures::future::join_all;
-use futures::StreamExt;
+use futures::future::{join_all, BoxFuture, Shared};
+use futures::{FutureExt, StreamExt};
 use once_cell::sync::Lazy;
+use regex::Regex;
 use reqwest::Client;
 use std::error::Error;
+use std::fmt;
 use std::fs::File;
 use std::io::BufWriter;
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::pin::Pin;
 use std::process::ExitStatus;
-use std::sync::Arc;
-use std::time::Duration;
-use std::{collections::HashMap, future::Future};
-use tokio::task::JoinHandle;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{collections::{HashMap, HashSet}, future::Future};
+use tokio::task::{AbortHandle, JoinHandle};
 use tokio::time::interval;
 use url::Url;
 
-static RUNNED_TARGETS: Lazy<DashMap<String, Arc<tokio::sync::Mutex<()>>>> = Lazy::new(DashMap::new);
+type TargetResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+static FAILED_STEPS: Lazy<Mutex<Vec<StepFailure>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static KEEP_GOING: AtomicBool = AtomicBool::new(false);
+static STOPPED: AtomicBool = AtomicBool::new(false);
+static BUILD_TASKS: Lazy<Mutex<Vec<AbortHandle>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+/// Steps which would run during a dry run, the steps depending on them would run too
+static WOULD_RUN_STEPS: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+static EXPLAIN: AtomicBool = AtomicBool::new(false);
+pub static HTTP_CLIENT: Lazy<Client> = Lazy::new(Client::new);
+static GLOB_HELPER_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\|\s*glob\b").unwrap());
 
 pub fn is_url(s: &str) -> bool {
     Url::parse(s).is_ok()
@@ -131,8 +148,7 @@ async fn download_file(
     }
 
     // Send GET request
-    let client = Client::new();
-    let mut request = client.get(url);
+    let mut request = HTTP_CLIENT.get(url);
 
     if let Some(username_secret) = maybe_username_secret {
         request = request.basic_auth(username_secret, maybe_password_secret);
@@ -155,6 +171,7 @@ async fn download_file(
     let mut downloaded: u64 = 0;
     let mut stream = response.bytes_stream();
     let mut last_log_time = std::time::Instant::now();
+    let mut last_percent = None;
 
     while let Some(chunk_result) = stream.next().await {
         let chunk = chunk_result?;
@@ -165,6 +182,11 @@ async fn download_file(
             percent = ((downloaded * 100) / total_size) as usize
         }
 
+        if last_percent != Some(percent) {
+            events::emit(Event::DownloadProgress { step_id, url, percent });
+            last_percent = Some(percent);
+        }
+
         let now = std::time::Instant::now();
         if now.duration_since(last_log_time) >= Duration::from_secs(10) {
             log::action_info!(step_id, action_id, "Percent {}% | {}", percent, description);
@@ -176,6 +198,23 @@ async fn download_file(
     Ok(())
 }
 
+/// Global variables available in the template language of every step
+pub fn get_default_replacements(emakefile_current_path: &str) -> HashMap<String, String> {
+    HashMap::from([
+        (String::from("EMAKE_WORKING_DIR"), cache::get_working_dir_path()),
+        (String::from("EMAKE_CWD_DIR"), get_cwd().to_string_lossy().to_string()),
+        (String::from("EMAKE_OUT_DIR"), cache::get_out_dir_path()),
+        (String::from("EMAKE_FILE_DIR"), PathBuf::from(&emakefile_current_path).parent().unwrap().to_string_lossy().to_string()),
+    ])
+}
+
+/// Variables available in a step, the global ones and the `item` of a step repeated by for_each
+pub fn get_step_replacements(step: &Step, emakefile_current_path: &str) -> HashMap<String, String> {
+    let mut replacements = get_default_replacements(emakefile_current_path);
+    replacements.extend(step.replacements.clone());
+    replacements
+}
+
 fn get_filename_from_url(url: &str) -> Option<String> {
     // Parse the URL
     if let Ok(parsed_url) = Url::parse(url) {
@@ -187,12 +226,28 @@ fn get_filename_from_url(url: &str) -> Option<String> {
     None
 }
 
-async fn get_real_in_files<'a>(
-    target_id: &'a str,
-    step_id: &'a str,
-    step: &'a Step,
-    emakefile_current_path: &'a str,
-) -> Vec<String> {
+/// Compile the in_files of a step, without downloading url files
+fn get_in_file_path(in_file: &emake::InFile) -> &String {
+    match in_file {
+        emake::InFile::Simple(src) => src,
+        emake::InFile::Detailed { file, .. } => file,
+    }
+}
+
+/// Compile an in_file, a helper can give a list of files
+fn compile_in_file(file_path: &str, emakefile_current_path: &str, replacements: &HashMap<String, String>) -> Vec<String> {
+    let compiled_in_file_string = emake::compiler::compile(file_path, emakefile_current_path, Some(replacements), None);
+
+    match serde_json::from_str::<Vec<String>>(&compiled_in_file_string) {
+        Ok(parsed_compiled_files) => parsed_compiled_files,
+        Err(_) => Vec::from([compiled_in_file_string]),
+    }
+}
+
+pub async fn get_compiled_in_files(
+    step: &Step,
+    emakefile_current_path: &str,
+) -> Vec<(String, Option<Credentials>)> {
     let plugin = ACTIONS_STORE.get(&step.action).expect(&format!(
         "Can't execute step \"{}\", we are not able to find the action used in this step",
         step.description.clone()
@@ -202,83 +257,87 @@ async fn get_real_in_files<'a>(
 
     plugin.insert_in_files(&step.action, &mut in_files).await;
 
-    let mut real_in_files = Vec::new();
-    let working_dir = cache::get_working_dir_path();
-    let out_dir = cache::get_out_dir_path();
-    let cwd = get_cwd().to_string_lossy().to_string();
-    let default_replacements = HashMap::from([
-        (String::from("EMAKE_WORKING_DIR"), working_dir.to_owned()),
-        (String::from("EMAKE_CWD_DIR"), cwd.to_owned()),
-        (String::from("EMAKE_OUT_DIR"), out_dir.to_owned()),
-        (String::from("EMAKE_FILE_DIR"), PathBuf::from(&emakefile_current_path).parent().unwrap().to_string_lossy().to_string()),
-    ]);
+    let mut compiled_in_files = Vec::new();
+    let default_replacements = get_step_replacements(step, emakefile_current_path);
 
-    let mut download_futures = Vec::new();
-    let mut downloadable_files_indices = HashMap::new();
-    let mut downloaded_files = Vec::new();
-    // Get in files modification date
     for in_file in &in_files {
-        let file_path;
-        let mut file_credentials = None;
-
-        match &in_file {
-            emake::InFile::Simple(src) => file_path = src,
-            emake::InFile::Detailed {
-                file: detailed_file,
-                credentials: detailed_credentials,
-            } => {
-                file_path = &detailed_file;
-                file_credentials = detailed_credentials.clone();
-            }
+        let file_credentials = match in_file {
+            emake::InFile::Simple(_) => None,
+            emake::InFile::Detailed { credentials, .. } => credentials.clone(),
+        };
+
+        for file in compile_in_file(get_in_file_path(in_file), emakefile_current_path, &default_replacements) {
+            compiled_in_files.push((file, file_credentials.clone()));
         }
+    }
 
-        let compiled_in_file_string = emake::compiler::compile(
-            &file_path,
-            emakefile_current_path,
-            Some(&default_replacements),
-            None
-        );
-        let mut files = Vec::from([compiled_in_file_string.clone()]);
+    compiled_in_files
+}
 
-        let parsed_compiled_files_result: Result<Vec<String>, _> =
-            serde_json::from_str(&compiled_in_file_string);
+/// Whether the in_files of a step can only be known once the previous steps ran:
+/// a glob, a pattern or a helper giving no file yet
+pub async fn has_dynamic_in_files(step: &Step, emakefile_current_path: &str) -> bool {
+    let Some(plugin) = ACTIONS_STORE.get(&step.action) else {
+        return false;
+    };
 
-        match parsed_compiled_files_result {
-            Ok(parsed_compiled_files) => files = parsed_compiled_files,
-            Err(_) => (),
+    let mut in_files = Vec::new();
+    plugin.insert_in_files(&step.action, &mut in_files).await;
+
+    let default_replacements = get_step_replacements(step, emakefile_current_path);
+    in_files.iter().any(|in_file| {
+        let file_path = get_in_file_path(in_file);
+        if GLOB_HELPER_REGEX.is_match(file_path) {
+            return true;
         }
 
-        for file in &files {
-            if graph::common::is_downloadable_file(&file) {
-                let filename = get_filename_from_url(&file).unwrap();
-                let mut output = PathBuf::from(cache::get_working_dir_path());
-                output.push(&filename);
-                let output_string = output.to_str().unwrap().to_string();
-                downloadable_files_indices.insert(file.clone(), output_string.clone());
-                if cache::has_file_changed(&output_string, step_id, &false) {
-                    log::trace!("Downloadable file {} changed", output_string);
-                    downloaded_files.push(file.clone());
-                    let file_clone = file.clone(); // required if file is &String
-                    let target_id_clone = String::from(target_id);
-                    let step_id_clone = String::from(step_id);
-                    let emakefile_current_path = emakefile_current_path.to_string();
-                    let file_credentials = file_credentials.clone();
-
-                    download_futures.push(tokio::spawn(async move {
-                        download_file(
-                            &target_id_clone,
-                            &step_id_clone,
-                            &file_clone,
-                            &output_string,
-                            &emakefile_current_path,
-                            &file_credentials,
-                        )
-                        .await
-                    }));
-                }
+        let files = compile_in_file(file_path, emakefile_current_path, &default_replacements);
+        files.is_empty() || files.iter().any(|file| file.contains(['*', '?']))
+    })
+}
+
+async fn get_real_in_files<'a>(
+    target_id: &'a str,
+    step_id: &'a str,
+    step: &'a Step,
+    emakefile_current_path: &'a str,
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let mut real_in_files = Vec::new();
+
+    let mut download_futures = Vec::new();
+    let mut downloadable_files_indices = HashMap::new();
+    let mut downloaded_files = Vec::new();
+    // Get in files modification date
+    for (file, file_credentials) in get_compiled_in_files(step, emakefile_current_path).await {
+        if graph::common::is_downloadable_file(&file) {
+            let filename = get_filename_from_url(&file).unwrap();
+            let mut output = PathBuf::from(cache::get_working_dir_path());
+            output.push(&filename);
+            let output_string = output.to_str().unwrap().to_string();
+            downloadable_files_indices.insert(file.clone(), output_string.clone());
+            if !is_dry_run() && cache::has_file_changed(&output_string, step_id, &false, &ChangeDetection::Mtime) {
+                log::trace!("Downloadable file {} changed", output_string);
+                downloaded_files.push(file.clone());
+                let file_clone = file.clone(); // required if file is &String
+                let target_id_clone = String::from(target_id);
+                let step_id_clone = String::from(step_id);
+                let emakefile_current_path = emakefile_current_path.to_string();
+                let file_credentials = file_credentials.clone();
+
+                download_futures.push(spawn(async move {
+                    download_file(
+                        &target_id_clone,
+                        &step_id_clone,
+                        &file_clone,
+                        &output_string,
+                        &emakefile_current_path,
+                        &file_credentials,
+                    )
+                    .await
+                }));
             }
         }
-        real_in_files.extend(files);
+        real_in_files.push(file);
     }
 
     // Run all downloads in parallel
@@ -294,12 +353,13 @@ async fn get_real_in_files<'a>(
         }
 
         if let Some(error) = maybe_error {
-            log::panic!(
+            return Err(format!(
                 "Error when downloading file {} from step {}: {}",
                 downloaded_files[index],
                 step_id,
                 error
-            );
+            )
+            .into());
         }
     }
 
@@ -317,10 +377,10 @@ async fn get_real_in_files<'a>(
             .collect();
     }
 
-    real_in_files
+    Ok(real_in_files)
 }
 
-async fn get_real_out_files<'a>(
+pub async fn get_real_out_files<'a>(
     _step_id: &'a str,
     step: &'a Step,
     emakefile_current_path: &'a str,
@@ -332,14 +392,7 @@ async fn get_real_out_files<'a>(
 
     let mut out_files = Vec::new();
     let mut real_out_files = Vec::new();
-    let working_dir = cache::get_working_dir_path();
-    let out_dir = cache::get_out_dir_path();
-    let default_replacements = HashMap::from([
-        (String::from("EMAKE_WORKING_DIR"), working_dir.to_owned()),
-        (String::from("EMAKE_CWD_DIR"), get_cwd().to_string_lossy().to_string()),
-        (String::from("EMAKE_OUT_DIR"), out_dir.to_owned()),
-        (String::from("EMAKE_FILE_DIR"), PathBuf::from(&emakefile_current_path).parent().unwrap().to_string_lossy().to_string()),
-    ]);
+    let default_replacements = get_step_replacements(step, emakefile_current_path);
 
     plugin.insert_out_files(&step.action, &mut out_files).await;
 
@@ -365,18 +418,22 @@ async fn get_real_out_files<'a>(
     real_out_files
 }
 
+/// Run the task of a step, None when it doesn't finish before the timeout and was stopped
 async fn run_with_progress<F, T>(
     task: F,
     log_every: Duration,
+    maybe_timeout: Option<Duration>,
     step_id: &str,
     step_description: &String,
-) -> T
+) -> Option<T>
 where
     F: Future<Output = T>,
 {
     let mut task = Box::pin(task);
     let start = tokio::time::Instant::now();
     let mut ticker = interval(log_every);
+    let deadline = tokio::time::sleep(maybe_timeout.unwrap_or(Duration::MAX));
+    tokio::pin!(deadline);
 
     loop {
         tokio::select! {
@@ -384,7 +441,10 @@ where
                 let elapsed = start.elapsed();
                 let elapsed_formatted = format_elapsed(elapsed);
                 log::step_info!(step_id, StepStatus::Finished, format!("{} after {}", step_description, elapsed_formatted));
-                return result;
+                return Some(result);
+            }
+            _ = &mut deadline, if maybe_timeout.is_some() => {
+                break;
             }
             _ = ticker.tick() => {
                 let elapsed = start.elapsed();
@@ -393,334 +453,706 @@ where
             }
         }
     }
+
+    // Kill the command and its children, the actions running in emake stop at their next check.
+    // The step may run again, its previous run must be over before.
+    let pids = utils::cancel_step(step_id);
+    let _ = task.await;
+    utils::wait_for_process_groups(&pids).await;
+    None
 }
 
-async fn run_step<'a>(
-    target_id: &'a str,
-    step_id: &'a str,
-    step: &'a Step,
-    emakefile_current_path: &'a str,
-    force_out_files: Option<Vec<String>>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
+async fn run_step(node: &StepNode, maybe_upstream_step_id: Option<&str>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let target_id = node.target_path.as_str();
+    let step_id = node.id.as_str();
+    let step = &node.step;
+    let emakefile_current_path = node.emakefile_path.as_str();
     let plugin = ACTIONS_STORE.get(&step.action).expect(&format!(
         "Can't execute step \"{}\", we are not able to find the plugin used in this step",
         step.description.clone()
     ));
     let step_description = step.description.clone();
-    log::step_info!(step_id, StepStatus::Running, step_description);
-
-    let working_dir = cache::get_working_dir_path();
-    let out_dir = cache::get_out_dir_path();
-    let cwd = get_cwd();
-    let default_replacements = HashMap::from([
-        (String::from("EMAKE_WORKING_DIR"), working_dir.to_owned()),
-        (String::from("EMAKE_CWD_DIR"), cwd.to_string_lossy().to_string()),
-        (String::from("EMAKE_OUT_DIR"), out_dir.to_owned()),
-        (String::from("EMAKE_FILE_DIR"), PathBuf::from(&emakefile_current_path).parent().unwrap().to_string_lossy().to_string()),
-    ]);
+    let start = Instant::now();
+    if !is_dry_run() {
+        log::step_info!(step_id, StepStatus::Running, step_description);
+    }
+
+    let default_replacements = get_step_replacements(step, emakefile_current_path);
+    let env = node.env.resolve(emakefile_current_path, &default_replacements);
     let real_in_files =
-        get_real_in_files(target_id, step_id, step, emakefile_current_path).await;
-    let plugin_out_files = get_real_out_files(step_id, step, emakefile_current_path).await;
-    let mut real_out_files = plugin_out_files.clone();
-    if force_out_files.is_some() {
-        real_out_files = force_out_files.unwrap();
-    }
-    let checksum_command = plugin.get_checksum();
-
-    let mut need_to_run_action =
-        (real_in_files.len() == 0 && real_out_files.len() == 0) || checksum_command.is_some();
-
-    for file in &real_in_files {
-        let file_changed = cache::has_file_changed(file, step_id, &true);
-        if file_changed {
-            log::trace!("Need to run the step {} because the in file {} has changed", step_id, file);
-            need_to_run_action = true;
-            break;
-        }
-    }
-    
-    if !need_to_run_action {
-        for file in &real_out_files {
-            let file_changed = cache::has_file_changed(file, step_id, &false);
-            if file_changed {
-                log::trace!("Need to run the step {} because the out file {} has changed", step_id, file);
-                need_to_run_action = true;
-                break;
-            }
-        }
+        get_real_in_files(target_id, step_id, step, emakefile_current_path).await?;
+    let real_out_files = get_real_out_files(step_id, step, emakefile_current_path).await;
+    let checksum_command = plugin.get_checksum(&step.action);
+    let change_detection = step.change_detection.unwrap_or(node.change_detection);
+
+    let action_footprint = compute_action_footprint(&step.action, &env, &step.replacements);
+    let implicit_files = state::get_implicit_files(step_id);
+    let registered_footprint = get_registered_action_footprint(step_id).await;
+    let mut checksum = None;
+    let mut recorded_checksum = None;
+    // The checksum is computed once, before the step runs, and a dry run doesn't execute anything
+    if let (Some(checksum_command), false) = (&checksum_command, is_dry_run()) {
+        checksum = compute_step_checksum(step_id, checksum_command, emakefile_current_path, &default_replacements, &env);
+        recorded_checksum = cache::get_cache_action_checksum(step_id).await;
     }
 
-    if let Some(checksum_command) = &checksum_command {
-        let mut maybe_checksum: Option<String> = None;
-        let (status, stdout, stderr) = utils::run_command(
-            checksum_command,
-            Path::new(emakefile_current_path),
-            Some(&default_replacements),
-        );
+    let step_state = StepState {
+        real_in_files: &real_in_files,
+        real_out_files: &real_out_files,
+        implicit_files: &implicit_files,
+        has_checksum_command: checksum_command.is_some(),
+        checksum: &checksum,
+        recorded_checksum: &recorded_checksum,
+        action_footprint: &action_footprint,
+        registered_footprint: &registered_footprint,
+        out_file_readers: &node.out_file_readers,
+    };
+    let mut run_reason = should_run(step_id, &step_state, &change_detection);
+
+    if is_dry_run() {
+        // The outputs of an upstream step would change, they are only compared with the disk state
+        if let (None, Some(upstream_step_id)) = (&run_reason, maybe_upstream_step_id) {
+            run_reason = Some(RunReason::UpstreamWouldRun(String::from(upstream_step_id)));
+        }
+        if run_reason.is_some() {
+            WOULD_RUN_STEPS.lock().unwrap().insert(String::from(step_id));
+        }
 
-        if ExitStatus::success(&status) {
-            maybe_checksum = Some(stdout);
-        } else {
-            log::warning!("Error when computing checksum of action {step_id}: {stderr}");
+        match &run_reason {
+            Some(reason) => events::emit(Event::StepWouldRun {
+                step_id,
+                target: target_id,
+                description: &step_description,
+                reason: reason.to_string(),
+            }),
+            None => events::emit(Event::StepSkipped {
+                step_id,
+                target: target_id,
+                description: &step_description,
+                reason: String::from("nothing changed"),
+            }),
         }
 
-        if let Some(checksum) = maybe_checksum {
-            if let Some(current_action_checksum) = cache::get_cache_action_checksum(step_id).await {
-                if checksum.trim().to_string() != current_action_checksum {
-                    log::info!(
-                        "Checksum change from {} to {}",
-                        current_action_checksum,
-                        checksum.trim().to_string()
-                    );
-                    need_to_run_action = true;
-                }
+        match &run_reason {
+            Some(reason) if is_explain() => {
+                log::step_info!(step_id, StepStatus::WouldRun, format!("{} ({})", step_description, reason))
             }
+            Some(_) => log::step_info!(step_id, StepStatus::WouldRun, step_description),
+            None if is_explain() => {
+                log::step_info!(step_id, StepStatus::Skipped, format!("{} (nothing changed)", step_description))
+            }
+            None => log::step_info!(step_id, StepStatus::Skipped, step_description),
         }
+        return Ok(());
     }
 
-    // Compute action footprint
-    let action_footprint = compute_action_footprint(&step.action);
-    let register_footprint = get_registered_action_footprint(&step_id).await;
-    if register_footprint.is_none() || action_footprint != register_footprint.unwrap() {
-        need_to_run_action = true;
+    if let Some(reason) = &run_reason {
+        events::emit(Event::StepStarted {
+            step_id,
+            target: target_id,
+            description: &step_description,
+            reason: reason.to_string(),
+        });
+
+        if is_explain() {
+            log::info!("Need to run the step {} because {}", step_id, reason);
+        } else {
+            log::trace!("Need to run the step {} because {}", step_id, reason);
+        }
     }
 
-    if need_to_run_action {
-        let run_result = run_with_progress(
-            plugin.run(
-                target_id,
-                step_id,
-                emakefile_current_path,
-                false,
-                &step.action,
+    if run_reason.is_some() {
+        // Outputs of steps depending on external state (checksum) can't be shared
+        let mut maybe_remote_cache_key = None;
+        if checksum_command.is_none() {
+            maybe_remote_cache_key = remote_cache::compute_key(
+                &action_footprint,
                 &real_in_files,
-                &plugin_out_files,
-                &working_dir,
-                Some(&default_replacements),
+                &real_out_files,
+                &remote_cache::get_step_env(&env),
+            );
+        }
+
+        if let Some(remote_cache_key) = &maybe_remote_cache_key {
+            if remote_cache::fetch(step_id, remote_cache_key).await {
+                register_action_footprint(step_id, &action_footprint).await;
+                register_files_cache(step_id, &real_in_files, &real_out_files, &change_detection);
+                log::step_info!(step_id, StepStatus::Finished, format!("{} (restored from remote cache)", step_description));
+                events::emit(Event::StepFinished {
+                    step_id,
+                    duration_ms: start.elapsed().as_millis(),
+                });
+                return Ok(());
+            }
+        }
+
+        let max_attempts = step.retry.as_ref().map(|retry| retry.attempts).unwrap_or(1);
+        let mut attempt = 1;
+        let run_start = Instant::now();
+        let run_result = loop {
+            utils::reset_cancelled_step(step_id);
+            let attempt_result = match run_with_progress(
+                plugin.run(
+                    ActionContext {
+                        target_id,
+                        step_id,
+                        emakefile_cwd: emakefile_current_path,
+                        maybe_replacements: Some(&default_replacements),
+                        env: &env,
+                    },
+                    &step.action,
+                    &real_in_files,
+                    &real_out_files,
+                ),
+                Duration::from_secs(10),
+                step.timeout,
+                step_id,
+                &step_description,
             )
-            ,
-            Duration::from_secs(10),
-            step_id,
-            &step_description,
-        )
-        .await
-        .map_err(|e| {
-                let msg = e.to_string();
-                Box::<dyn Error + Send + Sync>::from(msg)
-            });
+            .await
+            {
+                Some(result) => result.map_err(|e| match e.downcast_ref::<ActionError>() {
+                    Some(action_error) => action_error.clone(),
+                    None => ActionError {
+                        message: e.to_string(),
+                        exit_code: None,
+                        stderr: Vec::new(),
+                    },
+                }),
+                None => {
+                    Err(ActionError {
+                        message: format!("The step timed out after {}", format_elapsed(step.timeout.unwrap())),
+                        exit_code: None,
+                        stderr: Vec::new(),
+                    })
+                }
+            };
+
+            let timed_out = utils::is_step_cancelled(step_id);
+            match (attempt_result, &step.retry) {
+                (Ok(()), _) => break Ok(()),
+                (Err(error), Some(retry))
+                    if attempt < max_attempts && retry.should_retry(error.exit_code, timed_out) =>
+                {
+                    let delay = retry.get_delay(attempt);
+                    log::step_info!(
+                        step_id,
+                        StepStatus::Running,
+                        format!(
+                            "{} failed (attempt {}/{}), retrying in {}: {}",
+                            step_description,
+                            attempt,
+                            max_attempts,
+                            format_elapsed(delay),
+                            error
+                        )
+                    );
+                    events::emit(Event::StepRetried {
+                        step_id,
+                        attempt,
+                        attempts: max_attempts,
+                        delay_ms: delay.as_millis(),
+                        exit_code: error.exit_code,
+                        error: error.to_string(),
+                    });
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                (Err(mut error), _) => {
+                    if step.retry.is_some() {
+                        error.message = format!("{} (attempt {}/{})", error.message, attempt, max_attempts);
+                    }
+                    break Err(Box::new(error) as Box<dyn Error + Send + Sync>);
+                }
+            }
+        };
+        // A single span for all the attempts, the delays between them included
+        profiler::record_step(step_id, &step_description, run_start, Instant::now(), attempt);
 
         if !run_result.is_err() {
             // Register footprint
             register_action_footprint(&step_id, &action_footprint).await;
 
-            // Register files cache
-            for file in real_in_files {
-                let mut filename = file;
-
-                if is_url(&filename) {
-                    let encoded_filename = urlencoding::encode(&filename).to_string();
-                    filename = encoded_filename;
-                }
+            // Register files cache, with the files discovered while running the step
+            let implicit_files = state::get_implicit_files(step_id);
+            register_files_cache(
+                step_id,
+                &[real_in_files.clone(), implicit_files.in_files].concat(),
+                &[real_out_files.clone(), implicit_files.out_files].concat(),
+                &change_detection,
+            );
 
-                let file_absolute_path = String::from(
-                    get_absolute_file_path(&filename)
-                        .to_str()
-                        .unwrap(),
-                );
-                CACHE_IN_FILE_TO_UPDATE.insert((file_absolute_path, String::from(step_id)));
+            // Register the checksum the step ran with
+            if let Some(checksum) = &checksum {
+                cache::write_cache_action_checksum(step_id, checksum).await
             }
 
-            for file in &real_out_files {
-                let file_absolute_path = String::from(
-                    get_absolute_file_path( file)
-                        .to_str()
-                        .unwrap(),
-                );
-                CACHE_OUT_FILE_TO_UPDATE.insert((file_absolute_path, String::from(step_id)));
+            // Share outputs with other builds
+            if let Some(remote_cache_key) = &maybe_remote_cache_key {
+                remote_cache::store(step_id, remote_cache_key, &real_out_files).await;
             }
 
-            // Compute checksum
-            if let Some(checksum_command) = &checksum_command {
-                let mut maybe_checksum: Option<String> = None;
-                let (status, stdout, stderr) = utils::run_command(
-                    checksum_command,
-                    Path::new(emakefile_current_path),
-                    Some(&default_replacements),
-                );
-
-                if ExitStatus::success(&status) {
-                    maybe_checksum = Some(stdout);
-                } else {
-                    log::warning!("Error when computing checksum of action {step_id}: {stderr}");
-                }
-
-                if let Some(checksum) = maybe_checksum {
-                    cache::write_cache_action_checksum(step_id, &checksum.trim().to_string()).await
-                }
-            }
+            events::emit(Event::StepFinished {
+                step_id,
+                duration_ms: start.elapsed().as_millis(),
+            });
 
             // log::step_info!(step_id, StepStatus::Finished, step_description);
         }
 
         return run_result;
     } else {
+        if change_detection == ChangeDetection::Hash {
+            // Refresh modification times so the next build can use the fast path again
+            register_files_cache(
+                step_id,
+                &[real_in_files.clone(), implicit_files.in_files].concat(),
+                &[real_out_files.clone(), implicit_files.out_files].concat(),
+                &change_detection,
+            );
+        }
         log::step_info!(step_id, StepStatus::Skipped, step_description);
+        events::emit(Event::StepSkipped {
+            step_id,
+            target: target_id,
+            description: &step_description,
+            reason: String::from("nothing changed"),
+        });
     }
 
     Ok(())
 }
 
-pub fn run_target<'a>(
-    target_absolute_path: String,
-) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
-    Box::pin(async move {
-        if RUNNED_TARGETS.contains_key(&target_absolute_path) {
-            let mutex = RUNNED_TARGETS.get(&target_absolute_path).unwrap();
-            let _target_lock = mutex.lock().await;
-            return;
-        }
+/// Why a step needs to run
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunReason {
+    NoInputsOutputs,
+    InFileChanged(String),
+    ImplicitInFileChanged(String),
+    OutFileMissing(String),
+    OutFileChanged(String),
+    ChecksumMissing,
+    ChecksumChanged { previous: String, current: String },
+    ChecksumFailed,
+    FootprintMissing,
+    FootprintChanged,
+    UpstreamWouldRun(String),
+}
 
-        let mutex = Arc::new(tokio::sync::Mutex::new(()));
-        RUNNED_TARGETS.insert(target_absolute_path.clone(), mutex.clone());
-        let _target_lock = mutex.lock().await;
+impl fmt::Display for RunReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunReason::NoInputsOutputs => write!(f, "no in_files, out_files or checksum declared"),
+            RunReason::InFileChanged(file) => write!(f, "the in file {} has changed", file),
+            RunReason::ImplicitInFileChanged(file) => write!(f, "the file {} read by the step has changed", file),
+            RunReason::OutFileMissing(file) => write!(f, "the out file {} is missing", file),
+            RunReason::OutFileChanged(file) => write!(f, "the out file {} has changed", file),
+            RunReason::ChecksumMissing => write!(f, "no checksum has been recorded yet"),
+            RunReason::ChecksumChanged { previous, current } => {
+                write!(f, "the checksum changed from {} to {}", previous, current)
+            }
+            RunReason::ChecksumFailed => write!(f, "the checksum command failed"),
+            RunReason::FootprintMissing => write!(f, "the step has never run"),
+            RunReason::FootprintChanged => write!(f, "the step definition has changed"),
+            RunReason::UpstreamWouldRun(step_id) => write!(f, "the upstream step {} would run", step_id),
+        }
+    }
+}
 
-        let emakefile_path = to_emakefile_path(&target_absolute_path);
-        let emakefile = emake::loader::load_file(&emakefile_path.to_string_lossy().to_string());
-        let target_info = extract_info_from_path(
-            &target_absolute_path,
-            &emakefile_path.to_string_lossy().to_string(),
-        );
+/// Current state of a step, with the checksum and the footprint recorded by its last run
+pub struct StepState<'a> {
+    pub real_in_files: &'a [String],
+    pub real_out_files: &'a [String],
+    pub implicit_files: &'a ImplicitFiles,
+    pub has_checksum_command: bool,
+    pub checksum: &'a Option<String>,
+    pub recorded_checksum: &'a Option<String>,
+    pub action_footprint: &'a str,
+    pub registered_footprint: &'a Option<String>,
+    pub out_file_readers: &'a OutFileReaders,
+}
 
-        let target_absolute_path_clone = target_absolute_path.clone();
-        let maybe_target = emakefile.targets.get(&target_info.unwrap().target_name);
+/// Decide if a step needs to run, returns None when the step can be skipped.
+/// Only reads the cache, nothing is executed nor registered.
+pub fn should_run(step_id: &str, state: &StepState, change_detection: &ChangeDetection) -> Option<RunReason> {
+    let StepState {
+        real_in_files,
+        real_out_files,
+        implicit_files,
+        has_checksum_command,
+        checksum,
+        recorded_checksum,
+        action_footprint,
+        registered_footprint,
+        out_file_readers,
+    } = *state;
+    if real_in_files.is_empty() && real_out_files.is_empty() && !has_checksum_command {
+        return Some(RunReason::NoInputsOutputs);
+    }
 
-        if maybe_target.is_none() {
-            log::panic!("Target not found: {}", target_absolute_path_clone);
+    for file in real_in_files {
+        if cache::has_file_changed(file, step_id, &true, change_detection) {
+            return Some(RunReason::InFileChanged(file.clone()));
         }
-        let target = maybe_target.unwrap();
-
-        if let Some(deps) = &target.deps {
-            let mut dependencies_tasks: Vec<JoinHandle<()>> = Vec::new();
-            let parallel_deps = target.parallel_deps.unwrap_or(true);
+    }
 
-            for dependency in deps {
-                let dependency_target_path =
-                    get_absolute_target_path(dependency, &emakefile.path.clone().unwrap());
+    // A discovered file which doesn't exist anymore may have been replaced by another one
+    for file in &implicit_files.in_files {
+        if cache::has_file_changed(file, step_id, &false, change_detection) {
+            return Some(RunReason::ImplicitInFileChanged(file.clone()));
+        }
+    }
 
-                if parallel_deps {
-                    let dependency_target_path_clone = dependency_target_path.clone();
-                    let handle = tokio::spawn(async move {
-                        run_target(dependency_target_path_clone).await;
-                    });
-                    dependencies_tasks.push(handle);
-                } else {
-                    run_target(dependency_target_path.clone()).await;
+    for file in real_out_files.iter().chain(&implicit_files.out_files) {
+        if cache::has_file_changed(file, step_id, &false, change_detection) {
+            let absolute_path = get_absolute_file_path(file);
+            if !absolute_path.exists() {
+                // A later step reading the file may have moved or removed it
+                if is_read_by_up_to_date_step(out_file_readers.get(&absolute_path)) {
+                    continue;
                 }
+                return Some(RunReason::OutFileMissing(file.clone()));
             }
-            
-            if parallel_deps {
-                // Await all dependency tasks
-                futures::future::join_all(dependencies_tasks).await;
+            return Some(RunReason::OutFileChanged(file.clone()));
+        }
+    }
+
+    // The checksum command isn't run by a dry run
+    if has_checksum_command && !is_dry_run() {
+        match (checksum, recorded_checksum) {
+            (None, _) => return Some(RunReason::ChecksumFailed),
+            (Some(_), None) => return Some(RunReason::ChecksumMissing),
+            (Some(current), Some(previous)) if current != previous => {
+                return Some(RunReason::ChecksumChanged {
+                    previous: previous.clone(),
+                    current: current.clone(),
+                });
             }
+            _ => {}
         }
+    }
 
-        if let Some(steps) = &target.steps {
-            let mut steps_tasks: Vec<JoinHandle<(String, Result<(), Box<dyn Error + Send + Sync>>)>> =
-                Vec::new();
-
-            for (step_index, step) in steps.iter().enumerate() {
-                let step_index_string = format!("{}", step_index);
-                let step_id = target_absolute_path.clone() + "/" + step_index_string.as_str();
-                let step_id_clone: String = step_id.clone();
-                let target_id_clone = target_absolute_path.clone();
-                let step_clone = step.clone();
-                let emakefile_path_str = emakefile_path.to_string_lossy().to_string();
-                let emakefile_path_str_clone = emakefile_path_str.clone();
-
-                if target.parallel_steps.unwrap_or(false) {
-                    let fut = async move {
-                        let m = get_mutex_for_id(&step_id_clone).await;
-                        let _guard = m.lock().await;
-                        update_progress(true, false);
-                        let run_step_result = run_step(
-                            &target_id_clone,
-                            &step_id_clone,
-                            &step_clone,
-                            &emakefile_path_str,
-                            None,
-                        )
-                        .await;
-                        update_progress(false, true);
-                        (step_id_clone, run_step_result)
-                    };
-                    let handle: JoinHandle<(String, Result<(), Box<dyn Error + Send + Sync>>)> =
-                        tokio::spawn(fut);
-                    steps_tasks.push(handle);
-                } else {
-                    let mut step_out_files =
-                        get_real_out_files(&step_id, &step, &emakefile_path_str_clone).await;
-                    // Find last out_files
-                    let mut current_step_index = step_index.clone() + 1;
-                    while current_step_index < steps.len() {
-                        let current_step = &steps[current_step_index];
-                        let current_step_index_string = format!("{}", current_step_index);
-                        let current_step_id =
-                            target_absolute_path.clone() + "/" + current_step_index_string.as_str();
-                        let current_step_in_files = get_real_in_files(
-                            &target_id_clone,
-                            &current_step_id,
-                            current_step,
-                            &emakefile_path_str_clone,
-                        )
-                        .await;
-                        for step_out_file in &step_out_files {
-                            if current_step_in_files.contains(step_out_file) {
-                                step_out_files = get_real_out_files(
-                                    &current_step_id,
-                                    current_step,
-                                    &emakefile_path_str_clone,
-                                )
-                                .await;
-                                break;
-                            }
-                        }
-                        current_step_index += 1;
-                    }
+    match registered_footprint {
+        None => Some(RunReason::FootprintMissing),
+        Some(footprint) if footprint != action_footprint => Some(RunReason::FootprintChanged),
+        _ => None,
+    }
+}
 
-                    let m = get_mutex_for_id(&step_id_clone).await;
-                    let _guard = m.lock().await;
-                    update_progress(true, false);
-                    let run_step_result = run_step(
-                        &target_id_clone,
-                        &step_id_clone,
-                        &step_clone,
-                        &emakefile_path_str,
-                        Some(step_out_files),
-                    )
-                    .await;
-                    update_progress(false, true);
-                    if run_step_result.is_err() {
-                        log::panic!(
-                            "An error occured when running the step [{}] {}, the status code is not 0. Error: {}",
-                            step_id_clone,
-                            step.description,
-                            run_step_result.err().unwrap()
-                        );
-                    }
+/// Whether one of the steps reading an out file still has all its out files
+fn is_read_by_up_to_date_step(maybe_readers: Option<&Vec<Arc<StepOutputs>>>) -> bool {
+    maybe_readers.into_iter().flatten().any(|reader| {
+        !reader.out_files.is_empty()
+            && reader.out_files.iter().all(|file| {
+                if !cache::has_file_changed(file, &reader.step_id, &false, &reader.change_detection) {
+                    return true;
                 }
+                let absolute_path = get_absolute_file_path(file);
+                !absolute_path.exists() && is_read_by_up_to_date_step(reader.out_file_readers.get(&absolute_path))
+            })
+    })
+}
+
+fn compute_step_checksum(
+    step_id: &str,
+    checksum_command: &String,
+    emakefile_current_path: &str,
+    default_replacements: &HashMap<String, String>,
+    env: &StepEnv,
+) -> Option<String> {
+    let (status, stdout, stderr) = utils::run_command(
+        checksum_command,
+        Path::new(emakefile_current_path),
+        Some(default_replacements),
+        env,
+    );
+
+    if ExitStatus::success(&status) {
+        return Some(stdout.trim().to_string());
+    }
+
+    log::warning!("Error when computing checksum of action {step_id}: {stderr}");
+    None
+}
+
+fn register_files_cache(
+    step_id: &str,
+    real_in_files: &Vec<String>,
+    real_out_files: &Vec<String>,
+    change_detection: &ChangeDetection,
+) {
+    for file in real_in_files {
+        let mut filename = file.clone();
+
+        if is_url(&filename) {
+            let encoded_filename = urlencoding::encode(&filename).to_string();
+            filename = encoded_filename;
+        }
+
+        let file_absolute_path = String::from(
+            get_absolute_file_path(&filename)
+                .to_str()
+                .unwrap(),
+        );
+        CACHE_IN_FILE_TO_UPDATE.insert((file_absolute_path, String::from(step_id), *change_detection));
+    }
+
+    for file in real_out_files {
+        let file_absolute_path = String::from(
+            get_absolute_file_path( file)
+                .to_str()
+                .unwrap(),
+        );
+        CACHE_OUT_FILE_TO_UPDATE.insert((file_absolute_path, String::from(step_id), *change_detection));
+    }
+}
+
+/// A step which failed during the build, reported in the summary at the end of the build
+#[derive(Debug, Clone)]
+pub struct StepFailure {
+    pub step_id: String,
+    pub description: String,
+    pub exit_code: Option<i32>,
+    pub stderr: Vec<String>,
+}
+
+/// Spawn a task of the build, aborted when the build is cancelled
+fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let handle = tokio::spawn(future);
+    BUILD_TASKS.lock().unwrap().push(handle.abort_handle());
+    handle
+}
+
+/// Stop the current build: abort its tasks and kill the running commands
+pub fn cancel() {
+    for task in BUILD_TASKS.lock().unwrap().drain(..) {
+        task.abort();
+    }
+    utils::kill_running_processes();
+}
+
+/// Forget the targets built by a previous build, to build them again in the same process
+pub fn reset() {
+    BUILD_TASKS.lock().unwrap().clear();
+    STOPPED.store(false, Ordering::Relaxed);
+    FAILED_STEPS.lock().unwrap().clear();
+    WOULD_RUN_STEPS.lock().unwrap().clear();
+}
+
+pub fn set_dry_run(dry_run: bool, explain: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+    EXPLAIN.store(explain, Ordering::Relaxed);
+}
+
+fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+fn is_explain() -> bool {
+    EXPLAIN.load(Ordering::Relaxed)
+}
+
+pub fn set_keep_going(keep_going: bool) {
+    KEEP_GOING.store(keep_going, Ordering::Relaxed);
+}
+
+fn is_keep_going() -> bool {
+    KEEP_GOING.load(Ordering::Relaxed)
+}
+
+fn is_stopped() -> bool {
+    STOPPED.load(Ordering::Relaxed)
+}
+
+pub fn get_failed_steps() -> Vec<StepFailure> {
+    FAILED_STEPS.lock().unwrap().clone()
+}
+
+fn record_step_failure(step_id: &str, step: &Step, error: &(dyn Error + Send + Sync + 'static), duration: Duration) {
+    log::error!(
+        "An error occured when running the step [{}] {}. Error: {}",
+        step_id,
+        step.description,
+        error
+    );
+
+    let (exit_code, stderr) = match error.downcast_ref::<ActionError>() {
+        Some(action_error) => (action_error.exit_code, action_error.stderr.clone()),
+        None => (None, Vec::new()),
+    };
+
+    events::emit(Event::StepFailed {
+        step_id,
+        duration_ms: duration.as_millis(),
+        exit_code,
+        error: error.to_string(),
+    });
+
+    if let Some(log_path) = step_logs::get_build_log(step_id) {
+        let tail = step_logs::read_tail(&log_path, step_logs::FAILURE_TAIL_LINES);
+        if !tail.is_empty() {
+            log::info!("Last {} lines of the log {}", tail.len(), log_path.to_string_lossy());
+            for line in &tail {
+                log::info!("    \x1b[90m{}\x1b[0m", line);
             }
+        } else {
+            log::info!("Log of the step: {}", log_path.to_string_lossy());
+        }
+    }
 
-            let join_results = futures::future::join_all(steps_tasks).await;
-            for result in join_results {
-                let run_step_result = result.unwrap();
-                if run_step_result.1.is_err() {
-                    log::panic!(
-                        "An error occured when running the step [{}], the status code is not 0. Error: {}",
-                        run_step_result.0,
-                        run_step_result.1.err().unwrap()
-                    );
-                }
+    FAILED_STEPS.lock().unwrap().push(StepFailure {
+        step_id: String::from(step_id),
+        description: step.description.clone(),
+        exit_code,
+        stderr,
+    });
+
+    if !is_keep_going() {
+        STOPPED.store(true, Ordering::Relaxed);
+    }
+}
+
+type StepDone = Shared<BoxFuture<'static, bool>>;
+
+/// Steps of a target still to run, to report when the target starts and finishes
+struct TargetProgress {
+    remaining_steps: AtomicUsize,
+    started: AtomicBool,
+    failed: AtomicBool,
+}
+
+impl TargetProgress {
+    fn start(&self, target_path: &str) {
+        if !self.started.swap(true, Ordering::Relaxed) {
+            profiler::record_target_start(target_path);
+            events::emit(Event::TargetStarted { target: target_path });
+        }
+    }
+
+    fn step_done(&self, target_path: &str, success: bool) {
+        if !success {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+
+        let is_last_step = self.remaining_steps.fetch_sub(1, Ordering::Relaxed) == 1;
+        if is_last_step && self.started.load(Ordering::Relaxed) {
+            profiler::record_target_end(target_path);
+            events::emit(Event::TargetFinished {
+                target: target_path,
+                success: !self.failed.load(Ordering::Relaxed),
+            });
+        }
+    }
+}
+
+/// Run the steps of the graph, each step starts as soon as the steps it depends on are done
+pub async fn run_graph(mut graph: BuildGraph) -> TargetResult {
+    graph.link_steps().await;
+    profiler::record_graph(&graph);
+
+    let mut targets_progress = HashMap::new();
+    for (target_path, target) in &graph.targets {
+        targets_progress.insert(
+            target_path.clone(),
+            Arc::new(TargetProgress {
+                remaining_steps: AtomicUsize::new(target.steps.len()),
+                started: AtomicBool::new(false),
+                failed: AtomicBool::new(false),
+            }),
+        );
+    }
+
+    let mut steps_done: Vec<(String, StepDone)> = Vec::new();
+    for node in graph.steps {
+        let deps = node.deps.iter().map(|index| steps_done[*index].clone()).collect();
+        let step_id = node.id.clone();
+        let target_progress = targets_progress[&node.target_path].clone();
+        let handle = spawn(async move {
+            let success = run_step_node(&node, deps, &target_progress).await;
+            target_progress.step_done(&node.target_path, success);
+            success
+        });
+        steps_done.push((step_id, handle.map(|result| matches!(result, Ok(true))).boxed().shared()));
+    }
+
+    let results = join_all(steps_done.into_iter().map(|(_step_id, step_done)| step_done)).await;
+    if results.contains(&false) {
+        return Err("The build failed".into());
+    }
+
+    Ok(())
+}
+
+fn skip_step_node(node: &StepNode, reason: String) {
+    events::emit(Event::StepSkipped {
+        step_id: &node.id,
+        target: &node.target_path,
+        description: &node.step.description,
+        reason,
+    });
+}
+
+/// Wait for the dependencies of the step then run it, returns whether the step is done
+async fn run_step_node(node: &StepNode, deps: Vec<(String, StepDone)>, target_progress: &TargetProgress) -> bool {
+    for (dep_step_id, dep_done) in &deps {
+        if !dep_done.clone().await {
+            if is_keep_going() {
+                log::warning!(
+                    "The step {} is not run because the step {} it depends on failed",
+                    node.id,
+                    dep_step_id
+                );
+            }
+            skip_step_node(node, format!("the step {} it depends on failed", dep_step_id));
+            return false;
+        }
+    }
+    target_progress.start(&node.target_path);
+
+    let maybe_upstream_step_id = match is_dry_run() {
+        true => {
+            let would_run_steps = WOULD_RUN_STEPS.lock().unwrap();
+            deps.iter().map(|(dep_step_id, _dep_done)| dep_step_id.clone()).find(|dep_step_id| would_run_steps.contains(dep_step_id))
+        }
+        false => None,
+    };
+
+    for condition in &node.conditions {
+        match condition.evaluate(&node.emakefile_path) {
+            Ok(true) => {}
+            Ok(false) => {
+                let reason = format!("the condition `{}` is false", condition);
+                log::step_info!(&node.id, StepStatus::Skipped, format!("{} ({})", node.step.description, reason));
+                skip_step_node(node, reason);
+                return true;
             }
+            Err(error) => {
+                record_step_failure(&node.id, &node.step, Box::<dyn Error + Send + Sync>::from(error).as_ref(), Duration::ZERO);
+                return false;
+            }
+        }
+    }
+
+    scheduler::schedule(&node.id, &node.step, async {
+        // Without keep going, no new step starts once a step failed
+        if is_stopped() {
+            skip_step_node(node, String::from("the build stopped after a failure"));
+            return false;
+        }
+
+        update_progress(true, false);
+        let start = Instant::now();
+        let run_step_result = run_step(node, maybe_upstream_step_id.as_deref()).await;
+        update_progress(false, true);
+
+        if let Err(error) = &run_step_result {
+            record_step_failure(&node.id, &node.step, error.as_ref(), start.elapsed());
         }
+        run_step_result.is_ok()
     })
+    .await
 }
@@ -0,0 +1,88 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, OnceLock},
+};
+
+use once_cell::sync::Lazy;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, SemaphorePermit};
+
+use crate::{console::log, emake::{self, Step}, get_cwd};
+
+// Every step of a build goes through the scheduler before running.
+// A step takes `weight` slots (1 by default) among the `--jobs` slots of the build,
+// and one slot of its pool when it belongs to a pool declared in the root Emakefile.
+static JOBS: OnceLock<usize> = OnceLock::new();
+static JOBS_SEMAPHORE: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(get_jobs()));
+static POOLS: Lazy<HashMap<String, Arc<Semaphore>>> = Lazy::new(load_pools);
+
+/// Set the maximum number of steps running at the same time, must be called before the first step
+pub fn set_jobs(jobs: usize) {
+    if jobs == 0 {
+        log::panic!("The number of jobs must be greater than 0");
+    }
+
+    JOBS.set(jobs).ok();
+}
+
+pub fn get_jobs() -> usize {
+    *JOBS.get_or_init(num_cpus::get)
+}
+
+fn load_pools() -> HashMap<String, Arc<Semaphore>> {
+    let mut pools = HashMap::new();
+    let root_emakefile_path = get_cwd().join("Emakefile");
+    if !root_emakefile_path.exists() {
+        return pools;
+    }
+
+    let root_emakefile = emake::loader::load_file(&root_emakefile_path.to_string_lossy());
+    for (name, size) in root_emakefile.pools.unwrap_or_default() {
+        if size == 0 {
+            log::panic!("The pool {} must allow at least one step", name);
+        }
+        pools.insert(name, Arc::new(Semaphore::new(size)));
+    }
+
+    pools
+}
+
+struct Slots<'a> {
+    _jobs: SemaphorePermit<'a>,
+    _pool: Option<OwnedSemaphorePermit>,
+}
+
+async fn acquire(step_id: &str, step: &Step) -> Slots<'static> {
+    // Take the pool slot first so a step waiting for its pool doesn't hold job slots
+    let mut pool_permit = None;
+    if let Some(pool_name) = &step.pool {
+        match POOLS.get(pool_name) {
+            Some(pool) => pool_permit = Some(pool.clone().acquire_owned().await.unwrap()),
+            None => {
+                log::panic!(
+                    "The step {} uses the pool {} which is not declared in the pools of the root Emakefile",
+                    step_id,
+                    pool_name
+                );
+            }
+        }
+    }
+
+    // A step heavier than the whole build would wait forever
+    let weight = step.weight.unwrap_or(1).clamp(1, get_jobs() as u32);
+    let jobs_permit = JOBS_SEMAPHORE.acquire_many(weight).await.unwrap();
+
+    Slots {
+        _jobs: jobs_permit,
+        _pool: pool_permit,
+    }
+}
+
+/// Run a step once the slots it needs are available
+pub async fn schedule<F, T>(step_id: &str, step: &Step, task: F) -> T
+where
+    F: Future<Output = T>,
+{
+    let _slots = acquire(step_id, step).await;
+    task.await
+}
@@ -1,13 +1,13 @@
 use std::collections::HashSet;
 
 use crate::{
-    emake::{self, loader::extract_info_from_path, Target},
+    emake::{self, loader::extract_info_from_path, params, Target},
     graph::{
         generator::{get_absolute_target_path, to_emakefile_path},
     },
 };
 
-fn target_visitor<F>(
+pub fn target_visitor<F>(
     parent_target: &str,
     target_absolute_path: &str,
     visitor: &mut F,
@@ -15,22 +15,48 @@ fn target_visitor<F>(
 where
     F: FnMut(&str, &str, &Target),
 {
+    visit_target(parent_target, target_absolute_path, visitor, &mut Vec::new());
+}
+
+fn visit_target<F>(
+    parent_target: &str,
+    target_absolute_path: &str,
+    visitor: &mut F,
+    ancestors: &mut Vec<String>,
+)
+where
+    F: FnMut(&str, &str, &Target),
+{
+    // Stop on dependency cycles, they are reported by the graph validation
+    if ancestors.iter().any(|ancestor| ancestor == target_absolute_path) {
+        return;
+    }
+
     let emakefile_path = to_emakefile_path(target_absolute_path);
+    if !emakefile_path.exists() {
+        return;
+    }
+
     let emakefile = emake::loader::load_file(&emakefile_path.to_string_lossy().to_string());
+    // The parameters of a target don't change its dependencies
+    let base_target_path = params::split_target_path(target_absolute_path)
+        .map(|(base_target_path, _params)| base_target_path)
+        .unwrap_or(target_absolute_path.to_string());
     let target_info = extract_info_from_path(
-        target_absolute_path,
+        &base_target_path,
         &emakefile_path.to_string_lossy().to_string(),
     );
 
     if let Some(target) = emakefile.targets.get(&target_info.unwrap().target_name) {
         visitor(parent_target, target_absolute_path, target);
+        ancestors.push(target_absolute_path.to_string());
 
         if let Some(deps) = &target.deps {
             if target.parallel_deps.unwrap_or(true) {
                 for dep in deps {
                     let dep_target_absolute_path =
                         get_absolute_target_path(dep, &emakefile_path.to_string_lossy().to_string());
-                    target_visitor(target_absolute_path, &dep_target_absolute_path, visitor);
+                    visit_target(target_absolute_path, &dep_target_absolute_path, visitor, ancestors);
                 }
             } else {
                 let mut last_deps_path = target_absolute_path.to_string();
@@ -38,11 +64,13 @@ where
                 for dep in deps {
                     let dep_target_absolute_path =
                         get_absolute_target_path(dep, &emakefile_path.to_string_lossy().to_string());
-                    target_visitor(&last_deps_path, &dep_target_absolute_path, visitor);
+                    visit_target(&last_deps_path, &dep_target_absolute_path, visitor, ancestors);
                     last_deps_path = dep_target_absolute_path;
                 }
             }
         }
+
+        ancestors.pop();
     }
 }
 
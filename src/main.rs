@@ -2,26 +2,30 @@ mod actions;
 mod cache;
 mod commands;
 mod console;
+mod depfile;
 mod doc;
 mod emake;
 mod graph;
+mod remote_cache;
+mod report;
+mod sandbox;
 mod secrets;
+mod step_logs;
+mod tracer;
 mod utils;
 
 use clap::{arg, Command};
-use std::{env, fs, path::{Path, PathBuf}, sync::{Arc, OnceLock, RwLock}};
+use std::{env, fs, path::{Path, PathBuf}, sync::{OnceLock, RwLock}};
 
-use dashmap::{DashMap, DashSet};
+use dashmap::DashSet;
 use once_cell::sync::Lazy;
-use tokio::sync::Mutex;
 
-use crate::{actions::ActionsStore, secrets::SecretsStore};
+use crate::{actions::ActionsStore, emake::ChangeDetection, secrets::SecretsStore};
 
-pub static GLOBAL_MUTEXES: Lazy<DashMap<String, Arc<Mutex<()>>>> = Lazy::new(DashMap::new);
 pub static ACTIONS_STORE: Lazy<ActionsStore> = Lazy::new(|| actions::instanciate());
 pub static CREDENTIALS_STORE: Lazy<SecretsStore> = Lazy::new(|| secrets::instanciate());
-pub static CACHE_IN_FILE_TO_UPDATE: Lazy<DashSet<(String, String)>> = Lazy::new(DashSet::new);
-pub static CACHE_OUT_FILE_TO_UPDATE: Lazy<DashSet<(String, String)>> = Lazy::new(DashSet::new);
+pub static CACHE_IN_FILE_TO_UPDATE: Lazy<DashSet<(String, String, ChangeDetection)>> = Lazy::new(DashSet::new);
+pub static CACHE_OUT_FILE_TO_UPDATE: Lazy<DashSet<(String, String, ChangeDetection)>> = Lazy::new(DashSet::new);
 pub static CWD: OnceLock<RwLock<PathBuf>> = OnceLock::new();
 
 fn init_cwd(cwd: PathBuf) {
@@ -34,11 +38,11 @@ pub fn get_cwd() -> PathBuf {
 }
 
 
-pub async fn get_mutex_for_id(id: &str) -> Arc<Mutex<()>> {
-    GLOBAL_MUTEXES
-        .entry(id.to_string())
-        .or_insert_with(|| Arc::new(Mutex::new(())))
-        .clone()
+fn format_arg() -> clap::Arg {
+    arg!(--format <FORMAT> "Output format")
+        .required(false)
+        .value_parser(["text", "json"])
+        .default_value("text")
 }
 
 #[tokio::main(flavor = "multi_thread")]
@@ -58,7 +62,8 @@ async fn main() {
         )
         .subcommand(
             Command::new("clean")
-            .about("Clean cache")
+            .about("Clean cache, or only the steps of a target and its dependencies")
+            .arg(arg!([target] "Target to clean").required(false))
             .arg(arg!(--dry_run "List files to delete without deleting it").required(false))
             )
         .subcommand(
@@ -71,7 +76,60 @@ async fn main() {
         .subcommand(
             Command::new("build")
                 .about("Build a target")
-                .arg(arg!([target] "Target to build").required(true)),
+                .arg(arg!([targets]... "Targets to build, or target patterns like //services/...:test (default: the default key of the root Emakefile)").required(false))
+                .arg(
+                    arg!(-j --jobs <JOBS> "Maximum number of steps running at the same time (default: number of CPUs)")
+                        .required(false)
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(arg!(--"dry-run" "Show the steps which would run without running them").required(false))
+                .arg(arg!(--explain "Show why each step runs or is skipped").required(false))
+                .arg(arg!(-k --"keep-going" "Keep building the targets which don't depend on a failed step").required(false))
+                .arg(arg!(--events <FILE> "Write the build events as JSON lines in a file, or on stdout with -").required(false))
+                .arg(arg!(--profile <FILE> "Write a Chrome trace of the build and print its critical path").required(false))
+                .arg(
+                    arg!(--param <PARAM> "Parameter key=value available in the steps and selecting a value of the matrices, can be repeated")
+                        .required(false)
+                        .action(clap::ArgAction::Append),
+                )
+                .arg(
+                    arg!(--report <REPORT> "Write a report of the build, junit=<path> or html=<path>, can be repeated")
+                        .required(false)
+                        .action(clap::ArgAction::Append),
+                ),
+        )
+        .subcommand(
+            Command::new("watch")
+                .about("Build targets again each time one of their files changes")
+                .arg(arg!([targets]... "Targets to watch (default: the default key of the root Emakefile)").required(false)),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("List the targets, variables and secrets of the project")
+                .arg(format_arg()),
+        )
+        .subcommand(
+            Command::new("logs")
+                .about("Show the output of the last run of the steps of a target")
+                .arg(arg!([target] "Target of the steps").required(true))
+                .arg(arg!(--step <STEP> "Index of the step in the target").required(false))
+                .arg(arg!(-f --follow "Print the lines as they are written, until ctrl-c").required(false)),
+        )
+        .subcommand(
+            Command::new("query")
+                .about("Explore the dependencies between targets")
+                .subcommand(
+                    Command::new("deps")
+                        .about("List the targets a target depends on")
+                        .arg(arg!([target] "Target to analyze").required(true))
+                        .arg(format_arg()),
+                )
+                .subcommand(
+                    Command::new("rdeps")
+                        .about("List the targets depending on a target")
+                        .arg(arg!([target] "Target to analyze").required(true))
+                        .arg(format_arg()),
+                ),
         )
         .subcommand(Command::new("doc").about("Generate documentation"))
         .subcommand(
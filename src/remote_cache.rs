@@ -0,0 +1,227 @@
+use std::{collections::BTreeMap, error::Error, future::Future, path::PathBuf, pin::Pin};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::{
+    cache,
+    console::{
+        events::{self, Event},
+        log,
+    },
+    emake::{self, env::StepEnv, RemoteCacheConfig},
+    get_cwd,
+    graph::runner::get_default_replacements,
+};
+
+mod http;
+mod local;
+
+pub type BackendResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+pub type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = BackendResult<T>> + Send + 'a>>;
+
+/// A content-addressed storage for step outputs
+pub trait CacheBackend: Send + Sync {
+    fn get<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> BackendFuture<'a, Option<Vec<u8>>>;
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        content: Vec<u8>,
+    ) -> BackendFuture<'a, ()>;
+}
+
+struct RemoteCache {
+    backend: Box<dyn CacheBackend>,
+    read_only: bool,
+}
+
+static REMOTE_CACHE: Lazy<Option<RemoteCache>> = Lazy::new(instanciate);
+
+fn instanciate() -> Option<RemoteCache> {
+    let root_emakefile_path = get_cwd().join("Emakefile");
+    if !root_emakefile_path.exists() {
+        return None;
+    }
+
+    let root_emakefile_path = root_emakefile_path.to_string_lossy().to_string();
+    let root_emakefile = emake::loader::load_file(&root_emakefile_path);
+    let config: RemoteCacheConfig = root_emakefile.remote_cache?;
+    let replacements = get_default_replacements(&root_emakefile_path);
+    let read_only = config.read_only.unwrap_or(false);
+
+    let backend: Box<dyn CacheBackend> = match (&config.url, &config.path) {
+        (Some(url), None) => {
+            let url = emake::compiler::compile(url, &root_emakefile_path, Some(&replacements), None);
+            Box::new(http::Http::new(&url))
+        }
+        (None, Some(path)) => {
+            let path = emake::compiler::compile(path, &root_emakefile_path, Some(&replacements), None);
+            Box::new(local::Local::new(PathBuf::from(path)))
+        }
+        _ => {
+            log::panic!("The remote_cache must define either an url or a path");
+        }
+    };
+
+    Some(RemoteCache { backend, read_only })
+}
+
+#[derive(Serialize)]
+struct KeyContent<'a> {
+    footprint: &'a str,
+    in_files: BTreeMap<String, String>,
+    out_files: Vec<String>,
+    env: &'a BTreeMap<String, String>,
+}
+
+fn to_relative_path(file: &str) -> Option<String> {
+    let cwd = get_cwd();
+    PathBuf::from(file)
+        .strip_prefix(&cwd)
+        .ok()
+        .map(|relative_path| relative_path.to_string_lossy().to_string())
+}
+
+/// Environment of the step, part of the remote cache key.
+/// The variables set in `env` are already in the footprint, the values of the passthrough variables
+/// are added here. The other variables inherited from the environment of emake are not part of the key.
+pub fn get_step_env(step_env: &StepEnv) -> BTreeMap<String, String> {
+    let mut env = BTreeMap::from([
+        (String::from("os"), String::from(std::env::consts::OS)),
+        (String::from("arch"), String::from(std::env::consts::ARCH)),
+    ]);
+
+    if step_env.clear {
+        for name in &step_env.passthrough {
+            if let Ok(value) = std::env::var(name) {
+                env.insert(format!("env:{}", name), value);
+            }
+        }
+    }
+    env
+}
+
+/// Compute the key of the outputs of a step. Returns None when the step can't be shared,
+/// for example when a file is outside the project folder.
+pub fn compute_key(
+    footprint: &str,
+    in_files: &Vec<String>,
+    out_files: &Vec<String>,
+    env: &BTreeMap<String, String>,
+) -> Option<String> {
+    if REMOTE_CACHE.is_none() || out_files.is_empty() {
+        return None;
+    }
+
+    let mut in_files_hashes = BTreeMap::new();
+    for in_file in in_files {
+        let relative_path = to_relative_path(in_file)?;
+        let hash = cache::get_file_hash(in_file)?;
+        in_files_hashes.insert(relative_path, hash);
+    }
+
+    let mut relative_out_files = Vec::new();
+    for out_file in out_files {
+        relative_out_files.push(to_relative_path(out_file)?);
+    }
+
+    let key_content = KeyContent {
+        footprint,
+        in_files: in_files_hashes,
+        out_files: relative_out_files,
+        env,
+    };
+
+    let serialized = serde_json::to_vec(&key_content).unwrap();
+    Some(blake3::hash(&serialized).to_hex().to_string())
+}
+
+fn pack(out_files: &Vec<String>) -> BackendResult<Vec<u8>> {
+    let encoder = zstd::Encoder::new(Vec::new(), 3)?;
+    let mut builder = tar::Builder::new(encoder);
+    builder.follow_symlinks(false);
+
+    for out_file in out_files {
+        let relative_path = to_relative_path(out_file)
+            .ok_or(format!("The out file {} is outside the project", out_file))?;
+        let path = PathBuf::from(out_file);
+
+        if path.is_dir() {
+            builder.append_dir_all(&relative_path, &path)?;
+        } else {
+            builder.append_path_with_name(&path, &relative_path)?;
+        }
+    }
+
+    let encoder = builder.into_inner()?;
+    Ok(encoder.finish()?)
+}
+
+fn unpack(content: &[u8]) -> BackendResult<()> {
+    let decoder = zstd::Decoder::new(content)?;
+    let mut archive = tar::Archive::new(decoder);
+    archive.set_preserve_mtime(false);
+    archive.unpack(get_cwd())?;
+    Ok(())
+}
+
+/// Restore the outputs of a step from the remote cache. Returns true on cache hit.
+pub async fn fetch(step_id: &str, key: &str) -> bool {
+    let Some(remote_cache) = &*REMOTE_CACHE else {
+        return false;
+    };
+
+    fetch_from(remote_cache.backend.as_ref(), step_id, key).await
+}
+
+async fn fetch_from(backend: &dyn CacheBackend, step_id: &str, key: &str) -> bool {
+    match backend.get(key).await {
+        Ok(Some(content)) => match unpack(&content) {
+            Ok(()) => {
+                log::debug!("Remote cache hit for step {} with key {}", step_id, key);
+                events::emit(Event::CacheHit { step_id, key });
+                true
+            }
+            Err(error) => {
+                log::warning!("Can't restore outputs of step {} from remote cache: {}", step_id, error);
+                false
+            }
+        },
+        Ok(None) => {
+            log::debug!("Remote cache miss for step {} with key {}", step_id, key);
+            events::emit(Event::CacheMiss { step_id, key });
+            false
+        }
+        Err(error) => {
+            log::warning!("Can't read the remote cache for step {}: {}", step_id, error);
+            false
+        }
+    }
+}
+
+/// Upload the outputs of a step to the remote cache
+pub async fn store(step_id: &str, key: &str, out_files: &Vec<String>) {
+    let Some(remote_cache) = &*REMOTE_CACHE else {
+        return;
+    };
+
+    if remote_cache.read_only {
+        return;
+    }
+
+    if let Err(error) = store_to(remote_cache.backend.as_ref(), step_id, key, out_files).await {
+        log::warning!("Can't upload outputs of step {} to remote cache: {}", step_id, error);
+    }
+}
+
+async fn store_to(backend: &dyn CacheBackend, step_id: &str, key: &str, out_files: &Vec<String>) -> BackendResult<()> {
+    if out_files.iter().any(|out_file| !PathBuf::from(out_file).exists()) {
+        log::debug!("Step {} outputs not uploaded to the remote cache, an out file is missing", step_id);
+        return Ok(());
+    }
+
+    backend.put(key, pack(out_files)?).await
+}
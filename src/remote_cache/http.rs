@@ -0,0 +1,227 @@
+use reqwest::StatusCode;
+
+use crate::graph::runner::HTTP_CLIENT;
+
+use super::{BackendFuture, CacheBackend};
+
+/// Store outputs on an HTTP server with `GET <url>/<key>` and `PUT <url>/<key>`
+pub struct Http {
+    url: String,
+}
+
+impl Http {
+    pub fn new(url: &str) -> Http {
+        Http {
+            url: String::from(url.trim_end_matches('/')),
+        }
+    }
+
+    fn get_entry_url(&self, key: &str) -> String {
+        format!("{}/{}", self.url, key)
+    }
+}
+
+impl CacheBackend for Http {
+    fn get<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> BackendFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move {
+            let response = HTTP_CLIENT.get(self.get_entry_url(key)).send().await?;
+
+            if response.status() == StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+
+            if !response.status().is_success() {
+                return Err(format!("HTTP {}", response.status()).into());
+            }
+
+            Ok(Some(response.bytes().await?.to_vec()))
+        })
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        content: Vec<u8>,
+    ) -> BackendFuture<'a, ()> {
+        Box::pin(async move {
+            let response = HTTP_CLIENT
+                .put(self.get_entry_url(key))
+                .body(content)
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                return Err(format!("HTTP {}", response.status()).into());
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fs,
+        io::{BufRead, BufReader, Read, Write},
+        net::{TcpListener, TcpStream},
+        path::PathBuf,
+        sync::{Arc, Mutex, RwLock},
+        thread,
+    };
+
+    use super::Http;
+    use crate::remote_cache::{fetch_from, store_to, CacheBackend};
+
+    type Entries = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// A stand-in for the cache server, keeping the entries in memory
+    struct StandInServer {
+        url: String,
+        entries: Entries,
+    }
+
+    impl StandInServer {
+        fn start() -> StandInServer {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/emake", listener.local_addr().unwrap());
+            let entries = Entries::default();
+
+            let server_entries = entries.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let entries = server_entries.clone();
+                    thread::spawn(move || serve(stream, &entries));
+                }
+            });
+
+            StandInServer { url, entries }
+        }
+
+        fn get_entry(&self, key: &str) -> Option<Vec<u8>> {
+            self.entries.lock().unwrap().get(&format!("/emake/{}", key)).cloned()
+        }
+
+        fn set_entry(&self, key: &str, content: &[u8]) {
+            self.entries.lock().unwrap().insert(format!("/emake/{}", key), content.to_vec());
+        }
+    }
+
+    /// Answer the requests of a connection until the client closes it
+    fn serve(stream: TcpStream, entries: &Entries) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+
+        loop {
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+                return;
+            }
+            let mut request_parts = request_line.split_whitespace();
+            let method = request_parts.next().unwrap_or_default().to_string();
+            let path = request_parts.next().unwrap_or_default().to_string();
+
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                let header = header.trim_end();
+                if header.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let (status, content) = match method.as_str() {
+                "GET" => match entries.lock().unwrap().get(&path) {
+                    Some(content) => ("200 OK", content.clone()),
+                    None => ("404 Not Found", Vec::new()),
+                },
+                "PUT" => {
+                    entries.lock().unwrap().insert(path, body);
+                    ("201 Created", Vec::new())
+                }
+                _ => ("405 Method Not Allowed", Vec::new()),
+            };
+
+            write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n", status, content.len()).unwrap();
+            stream.write_all(&content).unwrap();
+        }
+    }
+
+    /// Project folder shared by the tests, each test uses its own files
+    fn get_project_dir() -> PathBuf {
+        let cwd = crate::CWD.get_or_init(|| {
+            let project_dir = std::env::temp_dir().join(format!("emake-remote-cache-{}", std::process::id()));
+            fs::create_dir_all(&project_dir).unwrap();
+            RwLock::new(project_dir)
+        });
+        cwd.read().unwrap().clone()
+    }
+
+    fn write_out_file(name: &str, content: &str) -> String {
+        let out_file = get_project_dir().join(name);
+        fs::write(&out_file, content).unwrap();
+        out_file.to_string_lossy().to_string()
+    }
+
+    #[tokio::test]
+    async fn unknown_key_is_a_miss() {
+        let server = StandInServer::start();
+        let backend = Http::new(&server.url);
+
+        assert_eq!(backend.get("unknown").await.unwrap(), None);
+        assert!(!fetch_from(&backend, "//targets:miss/0", "unknown").await);
+    }
+
+    #[tokio::test]
+    async fn uploaded_outputs_are_restored() {
+        let server = StandInServer::start();
+        let backend = Http::new(&format!("{}/", server.url));
+        let out_file = write_out_file("upload.txt", "compiled");
+
+        store_to(&backend, "//targets:upload/0", "upload", &Vec::from([out_file.clone()]))
+            .await
+            .unwrap();
+        assert!(server.get_entry("upload").is_some());
+
+        fs::remove_file(&out_file).unwrap();
+        assert!(fetch_from(&backend, "//targets:upload/0", "upload").await);
+        assert_eq!(fs::read_to_string(&out_file).unwrap(), "compiled");
+    }
+
+    #[tokio::test]
+    async fn missing_outputs_are_not_uploaded() {
+        let server = StandInServer::start();
+        let backend = Http::new(&server.url);
+        let out_file = get_project_dir().join("missing.txt").to_string_lossy().to_string();
+
+        store_to(&backend, "//targets:missing/0", "missing", &Vec::from([out_file]))
+            .await
+            .unwrap();
+        assert!(server.get_entry("missing").is_none());
+    }
+
+    #[tokio::test]
+    async fn corrupt_archive_is_not_a_hit() {
+        let server = StandInServer::start();
+        let backend = Http::new(&server.url);
+        // A valid archive would be extracted in the project folder
+        get_project_dir();
+        server.set_entry("corrupt", b"not a tar.zst archive");
+
+        assert_eq!(backend.get("corrupt").await.unwrap(), Some(b"not a tar.zst archive".to_vec()));
+        assert!(!fetch_from(&backend, "//targets:corrupt/0", "corrupt").await);
+    }
+}
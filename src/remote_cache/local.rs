@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+
+use super::{BackendFuture, CacheBackend};
+
+/// Store outputs inside a local or mounted directory
+pub struct Local {
+    path: PathBuf,
+}
+
+impl Local {
+    pub fn new(path: PathBuf) -> Local {
+        Local { path }
+    }
+
+    fn get_entry_path(&self, key: &str) -> PathBuf {
+        self.path.join(&key[0..2]).join(format!("{}.tar.zst", key))
+    }
+}
+
+impl CacheBackend for Local {
+    fn get<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> BackendFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move {
+            let entry_path = self.get_entry_path(key);
+            if !tokio::fs::try_exists(&entry_path).await? {
+                return Ok(None);
+            }
+
+            Ok(Some(tokio::fs::read(&entry_path).await?))
+        })
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        content: Vec<u8>,
+    ) -> BackendFuture<'a, ()> {
+        Box::pin(async move {
+            let entry_path = self.get_entry_path(key);
+            tokio::fs::create_dir_all(entry_path.parent().unwrap()).await?;
+
+            // Write then rename so concurrent readers never see a partial entry
+            let tmp_path = entry_path.with_extension(format!("{}.tmp", std::process::id()));
+            tokio::fs::write(&tmp_path, content).await?;
+            tokio::fs::rename(&tmp_path, &entry_path).await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+
+use crate::console::{events::Event, log};
+
+mod html;
+mod junit;
+
+// Reports are written at the end of the build from the events of the build.
+// Nothing is recorded when no report is asked.
+static RECORDING: AtomicBool = AtomicBool::new(false);
+static BUILD_START: OnceLock<(Instant, DateTime<Utc>)> = OnceLock::new();
+static STEPS: Lazy<Mutex<Vec<StepRecord>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static STEP_INDEXES: Lazy<Mutex<HashMap<String, usize>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static REPORTS: Lazy<Mutex<Vec<(ReportFormat, String)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Junit,
+    Html,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepOutcome {
+    Passed,
+    Skipped,
+    WouldRun,
+    Failed,
+}
+
+impl StepOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            StepOutcome::Passed => "passed",
+            StepOutcome::Skipped => "skipped",
+            StepOutcome::WouldRun => "would run",
+            StepOutcome::Failed => "failed",
+        }
+    }
+}
+
+/// What happened to a step during the build, times are relative to the start of the build
+#[derive(Debug, Clone)]
+pub struct StepRecord {
+    pub step_id: String,
+    pub target: String,
+    pub description: String,
+    pub outcome: StepOutcome,
+    pub reason: Option<String>,
+    pub start: Duration,
+    pub end: Duration,
+    pub exit_code: Option<i32>,
+    pub error: Option<String>,
+    /// Number of runs of the step, more than 1 when it was retried
+    pub attempts: u32,
+    pub stdout: Vec<String>,
+    pub stderr: Vec<String>,
+}
+
+impl StepRecord {
+    pub fn duration(&self) -> Duration {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+/// Parse a `--report <format>=<path>` argument
+pub fn add_report(report: &str) {
+    let Some((format, path)) = report.split_once('=') else {
+        log::panic!("Invalid report {}, expected <format>=<path> like junit=report.xml", report);
+    };
+
+    let format = match format {
+        "junit" => ReportFormat::Junit,
+        "html" => ReportFormat::Html,
+        _ => {
+            log::panic!("Unknown report format {}, expected junit or html", format);
+        }
+    };
+
+    REPORTS.lock().unwrap().push((format, String::from(path)));
+    start_recording();
+}
+
+pub fn start_recording() {
+    BUILD_START.get_or_init(|| (Instant::now(), Utc::now()));
+    RECORDING.store(true, Ordering::Relaxed);
+}
+
+pub fn get_build_start() -> DateTime<Utc> {
+    BUILD_START.get().map(|(_instant, date)| *date).unwrap_or_else(Utc::now)
+}
+
+fn elapsed() -> Duration {
+    BUILD_START
+        .get()
+        .map(|(instant, _date)| instant.elapsed())
+        .unwrap_or_default()
+}
+
+fn update_step<F>(step_id: &str, update: F)
+where
+    F: FnOnce(&mut StepRecord),
+{
+    let index = STEP_INDEXES.lock().unwrap().get(step_id).copied();
+    if let Some(index) = index {
+        update(&mut STEPS.lock().unwrap()[index]);
+    }
+}
+
+fn insert_step(record: StepRecord) {
+    let mut steps = STEPS.lock().unwrap();
+    STEP_INDEXES.lock().unwrap().insert(record.step_id.clone(), steps.len());
+    steps.push(record);
+}
+
+fn new_step(step_id: &str, target: &str, description: &str, outcome: StepOutcome, reason: String) -> StepRecord {
+    let now = elapsed();
+    StepRecord {
+        step_id: String::from(step_id),
+        target: String::from(target),
+        description: String::from(description),
+        outcome,
+        reason: Some(reason),
+        start: now,
+        end: now,
+        exit_code: None,
+        error: None,
+        attempts: 1,
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    }
+}
+
+/// Keep what the reports need from an event of the build
+pub fn record(event: &Event) {
+    if !RECORDING.load(Ordering::Relaxed) {
+        return;
+    }
+
+    match event {
+        Event::StepStarted {
+            step_id,
+            target,
+            description,
+            reason,
+        } => insert_step(new_step(step_id, target, description, StepOutcome::Passed, reason.clone())),
+        Event::StepSkipped {
+            step_id,
+            target,
+            description,
+            reason,
+        } => insert_step(new_step(step_id, target, description, StepOutcome::Skipped, reason.clone())),
+        Event::StepWouldRun {
+            step_id,
+            target,
+            description,
+            reason,
+        } => insert_step(new_step(step_id, target, description, StepOutcome::WouldRun, reason.clone())),
+        Event::StepFinished { step_id, .. } => update_step(step_id, |record| record.end = elapsed()),
+        Event::StepFailed {
+            step_id,
+            duration_ms,
+            exit_code,
+            error,
+        } => {
+            let now = elapsed();
+            let is_started = STEP_INDEXES.lock().unwrap().contains_key(*step_id);
+            if !is_started {
+                // The step failed while preparing its files, before being started
+                let (target, _index) = split_step_id(step_id);
+                let mut record = new_step(step_id, target, "", StepOutcome::Failed, String::new());
+                record.reason = None;
+                record.start = now.saturating_sub(Duration::from_millis(*duration_ms as u64));
+                insert_step(record);
+            }
+
+            update_step(step_id, |record| {
+                record.outcome = StepOutcome::Failed;
+                record.end = now;
+                record.exit_code = *exit_code;
+                record.error = Some(error.clone());
+            });
+        }
+        Event::StepRetried { step_id, attempt, .. } => update_step(step_id, |record| record.attempts = attempt + 1),
+        Event::Output { step_id, stream, line } => update_step(step_id, |record| {
+            if *stream == "stderr" {
+                record.stderr.push(String::from(*line));
+            } else {
+                record.stdout.push(String::from(*line));
+            }
+        }),
+        _ => {}
+    }
+}
+
+pub fn get_step_records() -> Vec<StepRecord> {
+    STEPS.lock().unwrap().clone()
+}
+
+/// Steps grouped by target, in the order the targets started
+pub fn group_by_target(steps: &Vec<StepRecord>) -> Vec<(String, Vec<&StepRecord>)> {
+    let mut targets: Vec<(String, Vec<&StepRecord>)> = Vec::new();
+    for step in steps {
+        match targets.iter_mut().find(|(target, _steps)| *target == step.target) {
+            Some((_target, target_steps)) => target_steps.push(step),
+            None => targets.push((step.target.clone(), Vec::from([step]))),
+        }
+    }
+
+    for (_target, target_steps) in &mut targets {
+        target_steps.sort_by_key(|step| step_index(&step.step_id));
+    }
+    targets
+}
+
+/// Target and index of a step id, `//folder:target/2` or `//folder:target/2/item` for a for_each
+fn split_step_id(step_id: &str) -> (&str, &str) {
+    let target_name_start = step_id.rfind(':').unwrap_or(0);
+    match step_id[target_name_start..].find('/') {
+        Some(separator) => (&step_id[..target_name_start + separator], &step_id[target_name_start + separator + 1..]),
+        None => (step_id, ""),
+    }
+}
+
+fn step_index(step_id: &str) -> usize {
+    let (_target, index) = split_step_id(step_id);
+    index.split('/').next().and_then(|index| index.parse().ok()).unwrap_or(0)
+}
+
+/// Write the reports asked with `--report`, called once the build is over
+pub fn write_reports() {
+    let reports = REPORTS.lock().unwrap().clone();
+    if reports.is_empty() {
+        return;
+    }
+
+    let steps = get_step_records();
+    let total_duration = elapsed();
+    for (format, path) in reports {
+        let content = match format {
+            ReportFormat::Junit => junit::render(&steps, total_duration),
+            ReportFormat::Html => html::render(&steps, total_duration),
+        };
+
+        match fs::write(&path, content) {
+            Ok(()) => log::info!("Report written in {}", path),
+            Err(error) => log::warning!("Can't write the report {}: {}", path, error),
+        }
+    }
+}
+
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters are not allowed in XML, ANSI colors of commands are dropped with them
+            character if character.is_control() && character != '\n' && character != '\t' => {}
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
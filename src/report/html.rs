@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use crate::utils::format_elapsed;
+
+use super::{escape_xml, get_build_start, group_by_target, StepOutcome, StepRecord};
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.4em; }
+.summary span { margin-right: 1.5em; }
+.timeline { position: relative; border: 1px solid #ccc; background: #fafafa; margin: 1em 0 2em 0; }
+.bar { position: absolute; height: 18px; border-radius: 3px; overflow: hidden; white-space: nowrap;
+       font-size: 11px; line-height: 18px; padding: 0 3px; box-sizing: border-box; min-width: 2px; }
+.passed { background: #7fc97f; }
+.failed { background: #f0605d; }
+.skipped { background: #ccc; }
+.would-run { background: #fdc086; }
+table { border-collapse: collapse; width: 100%; }
+th, td { text-align: left; padding: 4px 8px; border-bottom: 1px solid #eee; vertical-align: top; }
+td.status { font-weight: bold; }
+pre { margin: 0.5em 0; max-height: 30em; overflow: auto; background: #f4f4f4; padding: 0.5em; }
+"#;
+
+const LANE_HEIGHT: usize = 22;
+
+fn outcome_class(outcome: StepOutcome) -> &'static str {
+    match outcome {
+        StepOutcome::Passed => "passed",
+        StepOutcome::Failed => "failed",
+        StepOutcome::Skipped => "skipped",
+        StepOutcome::WouldRun => "would-run",
+    }
+}
+
+/// Put each step on the first lane free at its start, so parallel steps are displayed on separate lanes
+fn assign_lanes(steps: &Vec<&StepRecord>) -> (Vec<usize>, usize) {
+    let mut lanes_end: Vec<Duration> = Vec::new();
+    let mut lanes = Vec::new();
+
+    for step in steps {
+        let lane = match lanes_end.iter().position(|end| *end <= step.start) {
+            Some(lane) => lane,
+            None => {
+                lanes_end.push(Duration::ZERO);
+                lanes_end.len() - 1
+            }
+        };
+        lanes_end[lane] = step.end.max(step.start + Duration::from_millis(1));
+        lanes.push(lane);
+    }
+
+    (lanes, lanes_end.len())
+}
+
+fn render_timeline(steps: &[StepRecord], total_duration: Duration) -> String {
+    // Skipped steps take no time, they would only clutter the timeline
+    let mut timed_steps: Vec<&StepRecord> = steps
+        .iter()
+        .filter(|step| step.outcome == StepOutcome::Passed || step.outcome == StepOutcome::Failed)
+        .collect();
+    timed_steps.sort_by_key(|step| step.start);
+
+    let (lanes, lanes_count) = assign_lanes(&timed_steps);
+    let total = total_duration.as_secs_f64().max(0.001);
+
+    let mut html = format!(
+        "<div class=\"timeline\" style=\"height: {}px\">\n",
+        lanes_count.max(1) * LANE_HEIGHT + 4
+    );
+    for (step, lane) in timed_steps.iter().zip(lanes) {
+        let left = step.start.as_secs_f64() / total * 100.0;
+        let width = step.duration().as_secs_f64() / total * 100.0;
+        let title = format!(
+            "{} {} ({}, {})",
+            step.step_id,
+            step.description,
+            step.outcome.as_str(),
+            format_elapsed(step.duration())
+        );
+        html.push_str(&format!(
+            "  <div class=\"bar {}\" style=\"left: {:.3}%; width: {:.3}%; top: {}px\" title=\"{}\">{}</div>\n",
+            outcome_class(step.outcome),
+            left,
+            width,
+            lane * LANE_HEIGHT + 2,
+            escape_xml(&title),
+            escape_xml(&step.step_id)
+        ));
+    }
+    html.push_str("</div>\n");
+    html
+}
+
+fn render_output(title: &str, lines: &[String]) -> String {
+    if lines.is_empty() {
+        return String::new();
+    }
+
+    format!(
+        "<details><summary>{} ({} lines)</summary><pre>{}</pre></details>",
+        title,
+        lines.len(),
+        escape_xml(&lines.join("\n"))
+    )
+}
+
+/// A standalone page with the timeline of the build and the result of each step
+pub fn render(steps: &Vec<StepRecord>, total_duration: Duration) -> String {
+    let count = |outcome: StepOutcome| steps.iter().filter(|step| step.outcome == outcome).count();
+
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>emake build report</title>\n");
+    html.push_str(&format!("<style>{}</style>\n</head>\n<body>\n", STYLE));
+    html.push_str(&format!(
+        "<h1>emake build report</h1>\n<p class=\"summary\"><span>Started {}</span><span>Duration {}</span><span>{} passed</span><span>{} failed</span><span>{} skipped</span></p>\n",
+        get_build_start().format("%Y-%m-%d %H:%M:%S UTC"),
+        format_elapsed(total_duration),
+        count(StepOutcome::Passed),
+        count(StepOutcome::Failed),
+        count(StepOutcome::Skipped) + count(StepOutcome::WouldRun)
+    ));
+
+    html.push_str("<h2>Timeline</h2>\n");
+    html.push_str(&render_timeline(steps, total_duration));
+
+    html.push_str("<h2>Steps</h2>\n<table>\n<tr><th>Step</th><th>Description</th><th>Status</th><th>Duration</th><th>Details</th></tr>\n");
+    for (_target, target_steps) in group_by_target(steps) {
+        for step in target_steps {
+            let mut details = escape_xml(&step.reason.clone().unwrap_or_default());
+            if let Some(error) = &step.error {
+                details = escape_xml(error);
+            }
+            if step.attempts > 1 {
+                details.push_str(&format!(" ({} attempts)", step.attempts));
+            }
+            details.push_str(&render_output("stdout", &step.stdout));
+            details.push_str(&render_output("stderr", &step.stderr));
+
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td class=\"status {}\">{}</td><td>{}</td><td>{}</td></tr>\n",
+                escape_xml(&step.step_id),
+                escape_xml(&step.description),
+                outcome_class(step.outcome),
+                step.outcome.as_str(),
+                format_elapsed(step.duration()),
+                details
+            ));
+        }
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
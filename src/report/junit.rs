@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use super::{escape_xml, get_build_start, group_by_target, StepOutcome, StepRecord};
+
+fn count(steps: &[&StepRecord], outcome: StepOutcome) -> usize {
+    steps.iter().filter(|step| step.outcome == outcome).count()
+}
+
+/// Each target is a testsuite, each step a testcase
+pub fn render(steps: &Vec<StepRecord>, total_duration: Duration) -> String {
+    let targets = group_by_target(steps);
+    let all_steps: Vec<&StepRecord> = steps.iter().collect();
+    let timestamp = get_build_start().format("%Y-%m-%dT%H:%M:%S");
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites name=\"emake\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        all_steps.len(),
+        count(&all_steps, StepOutcome::Failed),
+        count(&all_steps, StepOutcome::Skipped) + count(&all_steps, StepOutcome::WouldRun),
+        total_duration.as_secs_f64()
+    ));
+
+    for (target, target_steps) in &targets {
+        let target_duration: Duration = target_steps.iter().map(|step| step.duration()).sum();
+        xml.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{:.3}\" timestamp=\"{}\">\n",
+            escape_xml(target),
+            target_steps.len(),
+            count(target_steps, StepOutcome::Failed),
+            count(target_steps, StepOutcome::Skipped) + count(target_steps, StepOutcome::WouldRun),
+            target_duration.as_secs_f64(),
+            timestamp
+        ));
+
+        for step in target_steps {
+            let name = if step.description.is_empty() {
+                step.step_id.clone()
+            } else {
+                format!("{} {}", step.step_id, step.description)
+            };
+            xml.push_str(&format!(
+                "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\">\n",
+                escape_xml(target),
+                escape_xml(&name),
+                step.duration().as_secs_f64()
+            ));
+            if step.attempts > 1 {
+                xml.push_str(&format!(
+                    "      <properties><property name=\"attempts\" value=\"{}\"/></properties>\n",
+                    step.attempts
+                ));
+            }
+
+            let reason = step.reason.clone().unwrap_or_default();
+            match step.outcome {
+                StepOutcome::Failed => {
+                    let failure_type = match step.exit_code {
+                        Some(exit_code) => format!("exit code {}", exit_code),
+                        None => String::from("error"),
+                    };
+                    let message = step.error.clone().unwrap_or_default();
+                    xml.push_str(&format!(
+                        "      <failure type=\"{}\" message=\"{}\">{}</failure>\n",
+                        escape_xml(&failure_type),
+                        escape_xml(&message),
+                        escape_xml(&step.stderr.join("\n"))
+                    ));
+                }
+                StepOutcome::Skipped => {
+                    xml.push_str(&format!("      <skipped message=\"{}\"/>\n", escape_xml(&reason)));
+                }
+                StepOutcome::WouldRun => {
+                    xml.push_str(&format!(
+                        "      <skipped message=\"{}\"/>\n",
+                        escape_xml(&format!("dry run, would run because {}", reason))
+                    ));
+                }
+                StepOutcome::Passed => {}
+            }
+
+            if !step.stdout.is_empty() {
+                xml.push_str(&format!(
+                    "      <system-out>{}</system-out>\n",
+                    escape_xml(&step.stdout.join("\n"))
+                ));
+            }
+            if !step.stderr.is_empty() {
+                xml.push_str(&format!(
+                    "      <system-err>{}</system-err>\n",
+                    escape_xml(&step.stderr.join("\n"))
+                ));
+            }
+            xml.push_str("    </testcase>\n");
+        }
+
+        xml.push_str("  </testsuite>\n");
+    }
+
+    xml.push_str("</testsuites>\n");
+    xml
+}
@@ -0,0 +1,173 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{cache, get_cwd, utils::get_absolute_file_path};
+
+// A sandboxed step runs in an execroot, a folder mirroring the project with only the declared
+// in_files linked in it. Undeclared project files don't exist there, so reading them fails
+// instead of silently producing a stale build. Declared out_files are copied back afterwards.
+const MISSING_FILE_ERRORS: [&str; 2] = ["No such file or directory", "not found"];
+
+pub struct Sandbox {
+    step_id: String,
+    project_dir: PathBuf,
+    root: PathBuf,
+}
+
+/// Folder of the execroot of a step
+fn get_sandbox_dir(step_id: &str) -> PathBuf {
+    PathBuf::from(cache::get_sandbox_dir_path()).join(step_id.trim_start_matches("//").replace(':', "/"))
+}
+
+#[cfg(unix)]
+fn link(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(not(unix))]
+fn link(_original: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "the sandbox is only supported on Linux"))
+}
+
+impl Sandbox {
+    /// Create a fresh execroot containing the in_files of the step
+    pub fn create(step_id: &str, in_files: &[String]) -> Result<Sandbox, String> {
+        if !cfg!(target_os = "linux") {
+            return Err(format!("The step {} can't run in a sandbox, the sandbox is only supported on Linux", step_id));
+        }
+
+        let sandbox = Sandbox {
+            step_id: String::from(step_id),
+            project_dir: get_cwd(),
+            root: get_sandbox_dir(step_id),
+        };
+        if sandbox.root.exists() {
+            fs::remove_dir_all(&sandbox.root)
+                .map_err(|error| format!("Can't remove the previous sandbox of the step {}: {}", step_id, error))?;
+        }
+        fs::create_dir_all(&sandbox.root)
+            .map_err(|error| format!("Can't create the sandbox of the step {}: {}", step_id, error))?;
+
+        // Parents first, the files of a linked folder are already available
+        let mut sorted_in_files: Vec<PathBuf> = in_files.iter().map(|file| get_absolute_file_path(file)).collect();
+        sorted_in_files.sort();
+        let mut linked: Vec<PathBuf> = Vec::new();
+        for in_file in sorted_in_files {
+            if !in_file.starts_with(&sandbox.project_dir) || linked.iter().any(|parent| in_file.starts_with(parent)) {
+                continue;
+            }
+
+            let sandbox_path = sandbox.to_sandbox_path(&in_file);
+            if let Some(parent) = sandbox_path.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|error| format!("Can't create the sandbox of the step {}: {}", step_id, error))?;
+            }
+            link(&in_file, &sandbox_path).map_err(|error| {
+                format!("Can't add the in file {} to the sandbox of the step {}: {}", in_file.to_string_lossy(), step_id, error)
+            })?;
+            linked.push(in_file);
+        }
+
+        Ok(sandbox)
+    }
+
+    fn to_sandbox_path(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.project_dir) {
+            Ok(relative_path) => self.root.join(relative_path),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+
+    /// Path seen by the command for a path of the project, paths outside of the project are kept
+    pub fn map_path(&self, path: &str) -> String {
+        let absolute_path = get_absolute_file_path(path);
+        if !absolute_path.starts_with(&self.project_dir) || absolute_path.starts_with(&self.root) {
+            return String::from(path);
+        }
+        self.to_sandbox_path(&absolute_path).to_string_lossy().to_string()
+    }
+
+    /// Path in the project of a path of the sandbox
+    pub fn unmap_path(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.root) {
+            Ok(relative_path) => self.project_dir.join(relative_path),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+
+    /// Create the folders of the out files, the command can write them as in the project
+    pub fn prepare_out_files(&self, out_files: &Vec<String>) -> Result<(), String> {
+        for out_file in out_files {
+            if let Some(parent) = self.to_sandbox_path(&get_absolute_file_path(out_file)).parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|error| format!("Can't create the sandbox of the step {}: {}", self.step_id, error))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the declared out files from the execroot to the project
+    pub fn collect_out_files(&self, out_files: &Vec<String>) -> Result<(), String> {
+        for out_file in out_files {
+            let project_path = get_absolute_file_path(out_file);
+            let sandbox_path = self.to_sandbox_path(&project_path);
+            let Ok(metadata) = fs::symlink_metadata(&sandbox_path) else {
+                return Err(format!(
+                    "The step {} didn't write its out file {} in its sandbox",
+                    self.step_id, out_file
+                ));
+            };
+            // A linked in file modified in place is already up to date in the project
+            if metadata.file_type().is_symlink() || sandbox_path == project_path {
+                continue;
+            }
+
+            if let Some(parent) = project_path.parent() {
+                fs::create_dir_all(parent).map_err(|error| format!("Can't create the folder of {}: {}", out_file, error))?;
+            }
+            if project_path.is_dir() {
+                fs::remove_dir_all(&project_path)
+            } else if project_path.exists() {
+                fs::remove_file(&project_path)
+            } else {
+                Ok(())
+            }
+            .map_err(|error| format!("Can't replace the out file {}: {}", out_file, error))?;
+
+            fs::rename(&sandbox_path, &project_path).map_err(|error| {
+                format!("Can't copy the out file {} from the sandbox of the step {}: {}", out_file, self.step_id, error)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Explain a failure caused by a file read by the command but not declared in its in_files
+    pub fn explain_failure(&self, stderr: &[String]) -> Option<String> {
+        let root = self.root.to_string_lossy().to_string();
+        let project_dir = self.project_dir.to_string_lossy().to_string();
+        let missing_files: Vec<String> = stderr
+            .iter()
+            .filter(|line| MISSING_FILE_ERRORS.iter().any(|error| line.contains(error)))
+            .map(|line| line.replace(&root, &project_dir))
+            .collect();
+
+        if missing_files.is_empty() {
+            return None;
+        }
+
+        Some(format!(
+            "The step {} runs in a sandbox containing only its in_files, it may read files which are not declared in its in_files:\n{}",
+            self.step_id,
+            missing_files.join("\n")
+        ))
+    }
+}
+
+// The execroot is removed however the step ends, its out files are collected before
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File},
+    io::{BufRead, BufReader, LineWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use once_cell::sync::Lazy;
+
+use crate::{
+    cache,
+    console::{events::Event, log},
+    emake, get_cwd,
+};
+
+// The full output of each step run is kept in .emake/logs/<step id>/<timestamp>.log,
+// so it can be read after the build even when parallel steps interleave their lines.
+// Only the last runs are kept, 5 unless the root Emakefile sets `kept_logs`.
+const DEFAULT_KEPT_RUNS: usize = 5;
+pub const FAILURE_TAIL_LINES: usize = 50;
+const HEADER_PREFIX: &str = "# ";
+
+static KEPT_RUNS: Lazy<usize> = Lazy::new(load_kept_runs);
+static OPEN_LOGS: Lazy<Mutex<HashMap<String, LineWriter<File>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// Log file of each step which ran during this build
+static BUILD_LOGS: Lazy<Mutex<HashMap<String, PathBuf>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn load_kept_runs() -> usize {
+    let root_emakefile_path = get_cwd().join("Emakefile");
+    if !root_emakefile_path.exists() {
+        return DEFAULT_KEPT_RUNS;
+    }
+
+    let root_emakefile = emake::loader::load_file(&root_emakefile_path.to_string_lossy());
+    match root_emakefile.kept_logs {
+        Some(0) => {
+            log::panic!("kept_logs must keep at least the log of the last run");
+        }
+        Some(kept_runs) => kept_runs,
+        None => DEFAULT_KEPT_RUNS,
+    }
+}
+
+/// Folder of the logs of a step, or of all the steps of a target
+pub fn get_logs_dir(id: &str) -> PathBuf {
+    // `:` can't be used in a file name on every system
+    PathBuf::from(cache::get_logs_dir_path()).join(id.trim_start_matches("//").replace(':', "/"))
+}
+
+fn open_log(step_id: &str, description: &str, reason: &str) {
+    let logs_dir = get_logs_dir(step_id);
+    if fs::create_dir_all(&logs_dir).is_err() {
+        return;
+    }
+
+    let now = chrono::Utc::now();
+    let log_path = logs_dir.join(format!("{}.log", now.format("%Y%m%dT%H%M%S%.3f")));
+    let Ok(file) = File::create(&log_path) else {
+        return;
+    };
+
+    let mut writer = LineWriter::new(file);
+    let _ = writeln!(writer, "{}step {} {}", HEADER_PREFIX, step_id, description);
+    let _ = writeln!(writer, "{}started at {}, {}", HEADER_PREFIX, now.to_rfc3339(), reason);
+
+    OPEN_LOGS.lock().unwrap().insert(String::from(step_id), writer);
+    BUILD_LOGS.lock().unwrap().insert(String::from(step_id), log_path);
+    remove_old_runs(&logs_dir);
+}
+
+fn write_header(writer: &mut LineWriter<File>, header: &str) {
+    for line in header.lines() {
+        let _ = writeln!(writer, "{}{}", HEADER_PREFIX, line);
+    }
+}
+
+fn close_log(step_id: &str, footer: String) {
+    if let Some(mut writer) = OPEN_LOGS.lock().unwrap().remove(step_id) {
+        write_header(&mut writer, &footer);
+    }
+}
+
+fn remove_old_runs(logs_dir: &Path) {
+    let mut runs = get_runs(logs_dir);
+    while runs.len() > *KEPT_RUNS {
+        let _ = fs::remove_file(runs.remove(0));
+    }
+}
+
+/// Log files of a step, from the oldest to the newest
+pub fn get_runs(logs_dir: &Path) -> Vec<PathBuf> {
+    let mut runs: Vec<PathBuf> = fs::read_dir(logs_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|extension| extension == "log"))
+        .collect();
+    runs.sort();
+    runs
+}
+
+/// Write the output of the steps in their log file
+pub fn record(event: &Event) {
+    match event {
+        Event::StepStarted {
+            step_id,
+            description,
+            reason,
+            ..
+        } => open_log(step_id, description, reason),
+        Event::Output { step_id, line, .. } => {
+            if let Some(writer) = OPEN_LOGS.lock().unwrap().get_mut(*step_id) {
+                let _ = writeln!(writer, "{}", line);
+            }
+        }
+        Event::StepFinished { step_id, duration_ms } => {
+            close_log(step_id, format!("finished after {}ms", duration_ms));
+        }
+        Event::StepFailed {
+            step_id,
+            duration_ms,
+            error,
+            ..
+        } => close_log(step_id, format!("failed after {}ms: {}", duration_ms, error)),
+        Event::StepRetried {
+            step_id,
+            attempt,
+            attempts,
+            delay_ms,
+            error,
+            ..
+        } => {
+            // The next attempts are written in the same log
+            if let Some(writer) = OPEN_LOGS.lock().unwrap().get_mut(*step_id) {
+                write_header(
+                    writer,
+                    &format!("attempt {}/{} failed: {}\nretrying in {}ms", attempt, attempts, error, delay_ms),
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Log file written by a step during this build
+pub fn get_build_log(step_id: &str) -> Option<PathBuf> {
+    BUILD_LOGS.lock().unwrap().get(step_id).cloned()
+}
+
+pub fn is_header(line: &str) -> bool {
+    line.starts_with(HEADER_PREFIX)
+}
+
+/// Last lines written by the command of a step
+pub fn read_tail(log_path: &Path, count: usize) -> Vec<String> {
+    let Ok(file) = File::open(log_path) else {
+        return Vec::new();
+    };
+
+    let mut tail = VecDeque::new();
+    for line in BufReader::new(file).lines().map_while(Result::ok) {
+        if is_header(&line) {
+            continue;
+        }
+        if tail.len() == count {
+            tail.pop_front();
+        }
+        tail.push_back(line);
+    }
+    tail.into()
+}
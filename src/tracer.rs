@@ -0,0 +1,221 @@
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    process::{Command, ExitStatus},
+};
+
+// With `track_deps: true`, the command of a shell step runs under ptrace and every file it opens
+// is recorded, so the files read or written without being declared can be checked on the next build.
+// Only the syscalls opening, creating, renaming or executing a file are decoded, on Linux x86_64.
+
+/// Files opened by a traced command and its children, as absolute paths
+#[derive(Debug, Default)]
+pub struct FileAccesses {
+    pub read: BTreeSet<PathBuf>,
+    pub written: BTreeSet<PathBuf>,
+}
+
+pub fn is_supported() -> bool {
+    cfg!(all(target_os = "linux", target_arch = "x86_64"))
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod ptrace_tracer {
+    use std::{
+        collections::HashMap,
+        os::unix::process::{CommandExt, ExitStatusExt},
+        path::PathBuf,
+        process::{Command, ExitStatus},
+    };
+
+    use nix::{
+        errno::Errno,
+        libc,
+        sys::{
+            ptrace::{self, Options},
+            signal::Signal,
+            wait::{waitpid, WaitPidFlag, WaitStatus},
+        },
+        unistd::Pid,
+    };
+
+    use super::FileAccesses;
+    use crate::utils::normalize_path;
+
+    const SYS_OPEN: u64 = 2;
+    const SYS_RENAME: u64 = 82;
+    const SYS_CREAT: u64 = 85;
+    const SYS_EXECVE: u64 = 59;
+    const SYS_OPENAT: u64 = 257;
+    const SYS_RENAMEAT: u64 = 264;
+    const SYS_RENAMEAT2: u64 = 316;
+    const SYS_OPENAT2: u64 = 437;
+    const MAX_PATH_LENGTH: usize = 4096;
+
+    enum Access {
+        Read(PathBuf),
+        Write(PathBuf),
+    }
+
+    /// Read a nul terminated string in the memory of the traced process
+    fn read_string(pid: Pid, address: u64) -> Option<String> {
+        let mut bytes = Vec::new();
+        let mut offset = 0;
+        while bytes.len() < MAX_PATH_LENGTH {
+            let word = ptrace::read(pid, (address + offset) as ptrace::AddressType).ok()?;
+            for byte in word.to_ne_bytes() {
+                if byte == 0 {
+                    return Some(String::from_utf8_lossy(&bytes).to_string());
+                }
+                bytes.push(byte);
+            }
+            offset += std::mem::size_of::<libc::c_long>() as u64;
+        }
+        None
+    }
+
+    fn resolve_path(pid: Pid, dir_fd: i32, address: u64) -> Option<PathBuf> {
+        let path = PathBuf::from(read_string(pid, address)?);
+        if path.is_absolute() {
+            return Some(normalize_path(path));
+        }
+
+        let base_dir = match dir_fd {
+            libc::AT_FDCWD => std::fs::read_link(format!("/proc/{}/cwd", pid)),
+            dir_fd => std::fs::read_link(format!("/proc/{}/fd/{}", pid, dir_fd)),
+        };
+        Some(normalize_path(base_dir.ok()?.join(path)))
+    }
+
+    fn is_write(flags: i32) -> bool {
+        flags & libc::O_ACCMODE != libc::O_RDONLY || flags & (libc::O_CREAT | libc::O_TRUNC) != 0
+    }
+
+    /// File accessed by the syscall the process is entering
+    fn decode_syscall(pid: Pid) -> Option<Access> {
+        let registers = ptrace::getregs(pid).ok()?;
+        let (arg0, arg1, arg2, arg3) = (registers.rdi, registers.rsi, registers.rdx, registers.r10);
+
+        match registers.orig_rax {
+            SYS_OPEN => {
+                let path = resolve_path(pid, libc::AT_FDCWD, arg0)?;
+                Some(if is_write(arg1 as i32) { Access::Write(path) } else { Access::Read(path) })
+            }
+            SYS_OPENAT => {
+                let path = resolve_path(pid, arg0 as i32, arg1)?;
+                Some(if is_write(arg2 as i32) { Access::Write(path) } else { Access::Read(path) })
+            }
+            SYS_OPENAT2 => {
+                // The flags are the first field of the open_how struct
+                let flags = ptrace::read(pid, arg2 as ptrace::AddressType).ok()?;
+                let path = resolve_path(pid, arg0 as i32, arg1)?;
+                Some(if is_write(flags as i32) { Access::Write(path) } else { Access::Read(path) })
+            }
+            SYS_CREAT => Some(Access::Write(resolve_path(pid, libc::AT_FDCWD, arg0)?)),
+            SYS_EXECVE => Some(Access::Read(resolve_path(pid, libc::AT_FDCWD, arg0)?)),
+            SYS_RENAME => Some(Access::Write(resolve_path(pid, libc::AT_FDCWD, arg1)?)),
+            SYS_RENAMEAT | SYS_RENAMEAT2 => Some(Access::Write(resolve_path(pid, arg2 as i32, arg3)?)),
+            _ => None,
+        }
+    }
+
+    fn has_succeeded(pid: Pid) -> bool {
+        ptrace::getregs(pid).is_ok_and(|registers| (registers.rax as i64) >= 0)
+    }
+
+    pub fn prepare(command: &mut Command) {
+        unsafe {
+            command.pre_exec(|| ptrace::traceme().map_err(std::io::Error::from));
+        }
+    }
+
+    pub fn trace(pid: u32) -> (ExitStatus, FileAccesses) {
+        let root_pid = Pid::from_raw(pid as i32);
+        let mut accesses = FileAccesses::default();
+        let mut status = ExitStatus::from_raw(libc::EXIT_FAILURE << 8);
+        // Only wait for the processes traced by this thread, other steps wait for their own processes
+        let wait_flags = Some(WaitPidFlag::__WALL | WaitPidFlag::__WNOTHREAD);
+
+        // The command stops before its first instruction
+        if waitpid(root_pid, wait_flags).is_err() {
+            return (status, accesses);
+        }
+        let options = Options::PTRACE_O_TRACESYSGOOD
+            | Options::PTRACE_O_TRACEFORK
+            | Options::PTRACE_O_TRACEVFORK
+            | Options::PTRACE_O_TRACECLONE
+            | Options::PTRACE_O_TRACEEXEC
+            | Options::PTRACE_O_EXITKILL;
+        let _ = ptrace::setoptions(root_pid, options);
+        let _ = ptrace::syscall(root_pid, None);
+
+        // Syscall being run by each process, a process stops when entering then leaving a syscall
+        let mut in_syscall: HashMap<Pid, Option<Access>> = HashMap::new();
+        loop {
+            match waitpid(Pid::from_raw(-1), wait_flags) {
+                Ok(WaitStatus::PtraceSyscall(pid)) => {
+                    match in_syscall.remove(&pid) {
+                        None => {
+                            in_syscall.insert(pid, decode_syscall(pid));
+                        }
+                        Some(Some(access)) if has_succeeded(pid) => match access {
+                            Access::Read(path) => {
+                                accesses.read.insert(path);
+                            }
+                            Access::Write(path) => {
+                                accesses.written.insert(path);
+                            }
+                        },
+                        Some(_) => {}
+                    }
+                    let _ = ptrace::syscall(pid, None);
+                }
+                Ok(WaitStatus::PtraceEvent(pid, _signal, _event)) => {
+                    let _ = ptrace::syscall(pid, None);
+                }
+                Ok(WaitStatus::Stopped(pid, signal)) => {
+                    // New processes start stopped, other signals are delivered to the process
+                    let signal = match signal {
+                        Signal::SIGSTOP | Signal::SIGTRAP => None,
+                        signal => Some(signal),
+                    };
+                    let _ = ptrace::syscall(pid, signal);
+                }
+                Ok(WaitStatus::Exited(pid, code)) => {
+                    in_syscall.remove(&pid);
+                    if pid == root_pid {
+                        status = ExitStatus::from_raw(code << 8);
+                    }
+                }
+                Ok(WaitStatus::Signaled(pid, signal, _core_dumped)) => {
+                    in_syscall.remove(&pid);
+                    if pid == root_pid {
+                        status = ExitStatus::from_raw(signal as i32);
+                    }
+                }
+                Ok(_) => {}
+                Err(Errno::EINTR) => {}
+                // No traced process left
+                Err(_) => break,
+            }
+        }
+
+        (status, accesses)
+    }
+}
+
+/// Make the command stop at its start so it can be traced, call `trace` once it is spawned
+pub fn prepare(command: &mut Command) {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    ptrace_tracer::prepare(command);
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    let _ = command;
+}
+
+/// Follow the command and its children until they exit, returns the exit status of the command
+pub fn trace(child: &mut std::process::Child) -> (ExitStatus, FileAccesses) {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    return ptrace_tracer::trace(child.id());
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    return (child.wait().expect("Failed to wait on child"), FileAccesses::default());
+}
@@ -1,4 +1,6 @@
-use crate::{emake, get_cwd};
+use crate::{console::log, emake::{self, env::StepEnv}, get_cwd};
+use dashmap::{DashMap, DashSet};
+use once_cell::sync::Lazy;
 use std::{
     collections::HashMap,
     io::Read,
@@ -10,6 +12,7 @@ pub fn run_command(
     command: &String,
     emakefile_path: &Path,
     replacements: Option<&HashMap<String, String>>,
+    env: &StepEnv,
 ) -> (
     std::process::ExitStatus,
     String,
@@ -30,12 +33,15 @@ pub fn run_command(
         arg = "/C";
     }
 
-    let mut output = Command::new(shell)
+    let mut process = Command::new(shell);
+    process
         .current_dir(get_cwd())
         .arg(arg) // Pass the command string to the shell
         .arg(compiled_command)
         .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
+        .stderr(Stdio::piped());
+    env.apply(&mut process);
+    let mut output = process
         .spawn()
         .expect("Failed to execute command");
 
@@ -92,6 +98,21 @@ pub fn get_absolute_file_path(file: &str) -> std::path::PathBuf {
     path
 }
 
+/// Remove `.` and `..` without resolving symbolic links, the file may not exist
+pub fn normalize_path(path: std::path::PathBuf) -> std::path::PathBuf {
+    let mut normalized = std::path::PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
 pub fn format_elapsed(duration: std::time::Duration) -> String {
     let total_micros = duration.as_micros();
     let total_seconds = total_micros / 1_000_000;
@@ -110,4 +131,145 @@ pub fn format_elapsed(duration: std::time::Duration) -> String {
         // Less than 1 second
         format!("0.{:06}s", micros)
     }
-}
\ No newline at end of file
+}
+
+/// Parse a duration like `90s`, `10m`, `1h30m` or `500ms`, a number alone is a number of seconds
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let mut duration = Duration::ZERO;
+    let mut rest = text;
+    if rest.is_empty() {
+        return Err(String::from("Empty duration, expected a duration like 90s, 10m or 1h30m"));
+    }
+
+    while !rest.is_empty() {
+        let number_length = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let unit_length = rest[number_length..]
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len() - number_length);
+        let (number, unit) = (&rest[..number_length], rest[number_length..number_length + unit_length].trim());
+        let Ok(number) = number.parse::<u64>() else {
+            return Err(format!("Invalid duration `{}`, expected a duration like 90s, 10m or 1h30m", text));
+        };
+
+        let maybe_part = match unit {
+            "ms" => Some(Duration::from_millis(number)),
+            "" | "s" => Some(Duration::from_secs(number)),
+            "m" => number.checked_mul(60).map(Duration::from_secs),
+            "h" => number.checked_mul(3600).map(Duration::from_secs),
+            _ => return Err(format!("Invalid unit `{}` in the duration `{}`, expected ms, s, m or h", unit, text)),
+        };
+        let Some(total) = maybe_part.and_then(|part| duration.checked_add(part)) else {
+            return Err(format!("The duration `{}` is too long", text));
+        };
+        duration = total;
+        rest = &rest[number_length + unit_length..];
+    }
+
+    Ok(duration)
+}
+
+/// Processes started by the steps with the id of their step, killed when a build is cancelled
+static RUNNING_PROCESSES: Lazy<DashMap<u32, String>> = Lazy::new(DashMap::new);
+/// Steps which timed out, their actions running in emake stop as soon as they can
+static CANCELLED_STEPS: Lazy<DashSet<String>> = Lazy::new(DashSet::new);
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(2);
+/// Time left to the killed processes to exit, after the grace period
+const PROCESS_EXIT_MARGIN: Duration = Duration::from_secs(1);
+const PROCESS_EXIT_POLL: Duration = Duration::from_millis(50);
+
+/// Run the command in its own process group, so the command and its children can be killed together
+pub fn set_own_process_group(command: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(not(unix))]
+    let _ = command;
+}
+
+pub fn register_process(step_id: &str, pid: u32) {
+    RUNNING_PROCESSES.insert(pid, String::from(step_id));
+}
+
+pub fn unregister_process(pid: u32) {
+    RUNNING_PROCESSES.remove(&pid);
+}
+
+/// Terminate a process and all its children, killed if still alive after a grace period
+pub fn kill_process_tree(pid: u32) {
+    #[cfg(unix)]
+    {
+        use nix::{sys::signal::{killpg, Signal}, unistd::Pid};
+
+        let process_group = Pid::from_raw(pid as i32);
+        if killpg(process_group, Signal::SIGTERM).is_err() {
+            return;
+        }
+
+        std::thread::spawn(move || {
+            std::thread::sleep(KILL_GRACE_PERIOD);
+            // Signal None only checks that the group still exists
+            if killpg(process_group, None).is_ok() {
+                let _ = killpg(process_group, Signal::SIGKILL);
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = Command::new("taskkill")
+            .args(["/T", "/F", "/PID", &pid.to_string()])
+            .output();
+    }
+}
+
+pub fn kill_running_processes() {
+    let pids: Vec<u32> = RUNNING_PROCESSES.iter().map(|entry| *entry.key()).collect();
+    for pid in pids {
+        kill_process_tree(pid);
+    }
+}
+
+/// Stop a step which timed out, its processes are killed.
+/// Returns the process groups of the step, to wait for them before running it again.
+pub fn cancel_step(step_id: &str) -> Vec<u32> {
+    CANCELLED_STEPS.insert(String::from(step_id));
+    let pids: Vec<u32> = RUNNING_PROCESSES
+        .iter()
+        .filter(|entry| entry.value() == step_id)
+        .map(|entry| *entry.key())
+        .collect();
+    for pid in &pids {
+        kill_process_tree(*pid);
+    }
+    pids
+}
+
+/// Wait until the process groups are gone, their processes are killed at the end of the grace period
+pub async fn wait_for_process_groups(pids: &[u32]) {
+    #[cfg(unix)]
+    {
+        use nix::{sys::signal::killpg, unistd::Pid};
+
+        let deadline = std::time::Instant::now() + KILL_GRACE_PERIOD + PROCESS_EXIT_MARGIN;
+        while pids.iter().any(|pid| killpg(Pid::from_raw(*pid as i32), None).is_ok()) {
+            if std::time::Instant::now() >= deadline {
+                log::warning!("The processes {:?} are still running after being killed", pids);
+                return;
+            }
+            tokio::time::sleep(PROCESS_EXIT_POLL).await;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = pids;
+}
+
+/// Called before running a step again after a timeout
+pub fn reset_cancelled_step(step_id: &str) {
+    CANCELLED_STEPS.remove(step_id);
+}
+
+pub fn is_step_cancelled(step_id: &str) -> bool {
+    CANCELLED_STEPS.contains(step_id)
+}